@@ -0,0 +1,205 @@
+use core::fmt::{self, Display, Formatter};
+use nix::sys::signal::Signal;
+use nix::sys::wait::WaitStatus;
+use std::io::{self, Write};
+use std::process::{Child, Command};
+use std::sync::Arc;
+use std::thread;
+
+use crate::tokenize::tokenize;
+
+pub enum BuildCommandResult {
+    Ready(Command),
+    Empty,
+    Incomplete,
+}
+
+pub fn build_command(shell: Option<&str>, command_text: &str) -> BuildCommandResult {
+    if command_text.trim().is_empty() {
+        return BuildCommandResult::Empty;
+    }
+
+    //
+    // Hand the whole command line to the shell,
+    // which takes care of pipes, quoting, globs,
+    // variables, etc.
+    //
+    if let Some(shell) = shell {
+        let mut command = Command::new(shell);
+        command.arg("-c").arg(command_text);
+        return BuildCommandResult::Ready(command);
+    }
+
+    let command_tokens = match tokenize(command_text) {
+        Some(command_tokens) => command_tokens,
+        None => return BuildCommandResult::Incomplete,
+    };
+    let mut command_tokens = command_tokens.into_iter();
+    let program = match command_tokens.next() {
+        Some(program) => program,
+        None => return BuildCommandResult::Empty,
+    };
+    let args = command_tokens;
+
+    let mut command = Command::new(program);
+    command.args(args);
+    BuildCommandResult::Ready(command)
+}
+
+///
+/// Replay piped input into a command on a separate
+/// thread, as the command may not read all of it
+/// until its output is read, or may never read it
+/// at all
+///
+pub fn write_piped_input(command_process: &mut Child, piped_input: &Arc<[u8]>) {
+    let mut command_stdin = match command_process.stdin.take() {
+        Some(command_stdin) => command_stdin,
+        None => return,
+    };
+
+    let piped_input = Arc::clone(piped_input);
+    thread::spawn(move || {
+        //
+        // Errors mean the command exited or closed
+        // its stdin early, like `head` does
+        //
+        let _ = command_stdin.write_all(&piped_input);
+    });
+}
+
+///
+/// What became of a running command
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommandStatus {
+    Exited(i32),
+    Signaled {
+        signal: Signal,
+        core_dumped: bool,
+    },
+    Stopped(Signal),
+    Continued,
+    /// Command couldn't be started, such as when
+    /// the program can't be found or executed
+    SpawnFailed(io::ErrorKind),
+}
+
+impl CommandStatus {
+    ///
+    /// Status reported by `waitpid`, if it's about
+    /// the command itself rather than tracing it
+    ///
+    pub fn from_wait_status(wait_status: WaitStatus) -> Option<Self> {
+        match wait_status {
+            WaitStatus::Exited(_, exit_code) => Some(Self::Exited(exit_code)),
+            WaitStatus::Signaled(_, signal, core_dumped) => Some(Self::Signaled {
+                signal,
+                core_dumped,
+            }),
+            WaitStatus::Stopped(_, signal) => Some(Self::Stopped(signal)),
+            WaitStatus::Continued(_) => Some(Self::Continued),
+            _ => None,
+        }
+    }
+
+    pub fn from_spawn_error(err: &io::Error) -> Self {
+        Self::SpawnFailed(err.kind())
+    }
+
+    ///
+    /// Whether the command is gone for good
+    ///
+    pub fn is_exit(&self) -> bool {
+        matches!(
+            self,
+            Self::Exited(..) | Self::Signaled { .. } | Self::SpawnFailed(..)
+        )
+    }
+
+    pub fn is_success(&self) -> bool {
+        matches!(self, Self::Exited(0))
+    }
+}
+
+impl Display for CommandStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Exited(exit_code) => write!(f, "exited with {}", exit_code),
+            Self::Signaled {
+                signal,
+                core_dumped: false,
+            } => write!(f, "killed by {}", signal),
+            Self::Signaled {
+                signal,
+                core_dumped: true,
+            } => write!(f, "killed by {} (core dumped)", signal),
+            Self::Stopped(signal) => write!(f, "stopped by {}", signal),
+            Self::Continued => write!(f, "continued"),
+            Self::SpawnFailed(io::ErrorKind::NotFound) => write!(f, "command not found"),
+            Self::SpawnFailed(kind) => write!(f, "{}", kind),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_command, BuildCommandResult, CommandStatus};
+    use nix::sys::signal::Signal;
+    use nix::sys::wait::WaitStatus;
+    use nix::unistd::Pid;
+    use std::env;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::process;
+
+    #[test]
+    fn describes_wait_statuses() {
+        let pid = Pid::from_raw(1);
+        let describe = |wait_status| {
+            CommandStatus::from_wait_status(wait_status)
+                .unwrap()
+                .to_string()
+        };
+
+        assert_eq!(describe(WaitStatus::Exited(pid, 1)), "exited with 1");
+        assert_eq!(
+            describe(WaitStatus::Signaled(pid, Signal::SIGSEGV, true)),
+            "killed by SIGSEGV (core dumped)"
+        );
+        assert_eq!(
+            describe(WaitStatus::Signaled(pid, Signal::SIGPIPE, false)),
+            "killed by SIGPIPE"
+        );
+        assert_eq!(
+            describe(WaitStatus::Stopped(pid, Signal::SIGTSTP)),
+            "stopped by SIGTSTP"
+        );
+        assert_eq!(describe(WaitStatus::Continued(pid)), "continued");
+        assert_eq!(
+            CommandStatus::from_wait_status(WaitStatus::StillAlive),
+            None
+        );
+    }
+
+    #[test]
+    fn describes_spawn_failures() {
+        let path = env::temp_dir().join(format!("pvw-not-executable-{}", process::id()));
+        fs::write(&path, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let describe = |command_text: &str| match build_command(None, command_text) {
+            BuildCommandResult::Ready(mut command) => {
+                let err = command.spawn().expect_err("spawn to fail");
+                CommandStatus::from_spawn_error(&err).to_string()
+            }
+            _ => panic!("command to be ready"),
+        };
+        let not_executable = describe(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(not_executable, "permission denied");
+        assert_eq!(describe("/"), "permission denied");
+        assert_eq!(describe("pvw-no-such-program"), "command not found");
+    }
+}
@@ -8,16 +8,18 @@ type ThreadPanicError = Box<dyn Any + Send + 'static>;
 
 #[derive(Debug)]
 pub enum Error {
+    ArgumentError(lexopt::Error),
     ChannelRecvError,
     ChannelSendError,
     IOError(io::Error),
     NixError(nix::Error),
-    ThreadPanicError(ThreadPanicError),
+    ThreadPanicError(#[allow(dead_code)] ThreadPanicError),
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
+            Self::ArgumentError(err) => err.fmt(f),
             Self::ChannelRecvError => write!(f, "ChannelRecvError"),
             Self::ChannelSendError => write!(f, "ChannelSendError"),
             Self::IOError(err) => err.fmt(f),
@@ -29,6 +31,12 @@ impl Display for Error {
 
 impl error::Error for Error {}
 
+impl From<lexopt::Error> for Error {
+    fn from(err: lexopt::Error) -> Self {
+        Self::ArgumentError(err)
+    }
+}
+
 impl From<mpsc::RecvError> for Error {
     fn from(_err: mpsc::RecvError) -> Self {
         Self::ChannelRecvError
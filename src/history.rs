@@ -0,0 +1,111 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+///
+/// Accepted commands, oldest first, kept in
+/// `$XDG_STATE_HOME/pvw/history`
+///
+pub struct History {
+    path: Option<PathBuf>,
+    entries: Vec<String>,
+}
+
+impl History {
+    ///
+    /// History from the file, or none at all if it
+    /// can't be read, since the preview works just
+    /// as well without it
+    ///
+    pub fn load() -> Self {
+        let path = history_path();
+        let entries = match path.as_ref().map(fs::read) {
+            Some(Ok(contents)) => String::from_utf8_lossy(&contents)
+                .lines()
+                .map(String::from)
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        Self { path, entries }
+    }
+
+    #[cfg(test)]
+    pub fn in_memory(entries: &[&str]) -> Self {
+        Self {
+            path: None,
+            entries: entries.iter().map(|&entry| String::from(entry)).collect(),
+        }
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    ///
+    /// Add the command, saving it to the file
+    /// where possible, as failing to save is no
+    /// reason to lose the command itself
+    ///
+    pub fn add(&mut self, command_text: &str) {
+        //
+        // One command per line in the history file
+        //
+        let command_text = command_text.replace(['\r', '\n'], " ");
+        let command_text = command_text.trim();
+        if command_text.is_empty() || self.entries.last().map(String::as_str) == Some(command_text)
+        {
+            return;
+        }
+
+        self.entries.push(String::from(command_text));
+
+        if let Some(path) = &self.path {
+            let _ = append_entry(path, command_text);
+        }
+    }
+
+    ///
+    /// Index of the newest entry before `before`
+    /// which contains `query`
+    ///
+    pub fn search_backward(&self, query: &str, before: usize) -> Option<usize> {
+        self.entries[..before]
+            .iter()
+            .rposition(|entry| entry.contains(query))
+    }
+}
+
+fn append_entry(path: &Path, command_text: &str) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = File::options().create(true).append(true).open(path)?;
+    writeln!(file, "{}", command_text)
+}
+
+fn history_path() -> Option<PathBuf> {
+    let state_home = match env::var_os("XDG_STATE_HOME") {
+        Some(state_home) if !state_home.is_empty() => PathBuf::from(state_home),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".local/state"),
+    };
+
+    Some(state_home.join("pvw/history"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::History;
+    use std::path::PathBuf;
+
+    #[test]
+    fn keeps_commands_which_fail_to_save() {
+        let mut history = History {
+            path: Some(PathBuf::from("/proc/pvw-no-such-dir/history")),
+            entries: Vec::new(),
+        };
+        history.add("echo hi");
+        assert_eq!(history.entries(), ["echo hi"]);
+    }
+}
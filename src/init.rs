@@ -0,0 +1,17 @@
+const BASH: &str = include_str!("init/pvw.bash");
+const FISH: &str = include_str!("init/pvw.fish");
+const ZSH: &str = include_str!("init/pvw.zsh");
+
+///
+/// Key binding widget for a shell, which opens
+/// pvw on the command being typed and puts the
+/// accepted command back on the prompt
+///
+pub fn init_script(shell: &str) -> Option<&'static str> {
+    match shell {
+        "bash" => Some(BASH),
+        "fish" => Some(FISH),
+        "zsh" => Some(ZSH),
+        _ => None,
+    }
+}
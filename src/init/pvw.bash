@@ -0,0 +1,22 @@
+#
+# pvw key bindings for Bash
+#
+# Add to ~/.bashrc:
+#
+#     eval "$(pvw init bash)"
+#
+# Then press Ctrl-X Ctrl-P to preview the
+# command being typed. Enter puts the final
+# command back on the prompt, Escape leaves
+# it as it was.
+#
+
+__pvw_widget() {
+  local command
+  command=$(pvw --shell=bash --print=command -- "$READLINE_LINE") || return
+  READLINE_LINE=$command
+  READLINE_POINT=${#command}
+}
+
+bind -m emacs-standard -x '"\C-x\C-p": __pvw_widget'
+bind -m vi-insert -x '"\C-x\C-p": __pvw_widget'
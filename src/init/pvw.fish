@@ -0,0 +1,23 @@
+#
+# pvw key bindings for Fish
+#
+# Add to ~/.config/fish/config.fish:
+#
+#     pvw init fish | source
+#
+# Then press Ctrl-X Ctrl-P to preview the
+# command being typed. Enter puts the final
+# command back on the prompt, Escape leaves
+# it as it was.
+#
+
+function __pvw_widget
+    set -l command (pvw --shell=fish --print=command -- (commandline | string collect))
+    and commandline --replace -- (string join \n -- $command)
+    commandline -f repaint
+end
+
+bind \cx\cp __pvw_widget
+if bind -M insert >/dev/null 2>&1
+    bind -M insert \cx\cp __pvw_widget
+end
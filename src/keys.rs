@@ -0,0 +1,409 @@
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags};
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::fd::AsRawFd;
+use std::str;
+use std::time::Duration;
+
+use crate::result::Result;
+
+///
+/// How long to wait for the rest of an escape
+/// sequence before taking an Escape as being
+/// pressed on its own
+///
+const ESCAPE_TIMEOUT: Duration = Duration::from_millis(50);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    /// Control with a lowercase letter or symbol,
+    /// such as `Ctrl('a')`
+    Ctrl(char),
+    Alt(char),
+    Enter,
+    Escape,
+    Tab,
+    BackTab,
+    Backspace,
+    AltBackspace,
+    Up,
+    Down,
+    Left,
+    Right,
+    CtrlLeft,
+    CtrlRight,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Insert,
+    Delete,
+    F(u8),
+    /// Text pasted in bracketed paste mode
+    Paste(String),
+    /// Escape sequence which isn't recognized
+    Unknown,
+}
+
+///
+/// Decodes raw terminal input into keys
+///
+pub struct KeyDecoder {
+    input: File,
+    buffer: Vec<u8>,
+    is_eof: bool,
+}
+
+impl KeyDecoder {
+    pub fn new(input: File) -> Self {
+        Self {
+            input,
+            buffer: Vec::new(),
+            is_eof: false,
+        }
+    }
+
+    ///
+    /// Wait up to `timeout` for a key to be
+    /// pressed, without taking it
+    ///
+    /// Also true once input has ended, so the
+    /// caller goes on to find out.
+    ///
+    pub fn wait_for_key(&self, timeout: Duration) -> Result<bool> {
+        if !self.buffer.is_empty() || self.is_eof {
+            return Ok(true);
+        }
+
+        self.wait_for_input(timeout)
+    }
+
+    fn next_key(&mut self) -> Result<Option<Key>> {
+        loop {
+            if self.buffer.is_empty() {
+                if self.is_eof {
+                    return Ok(None);
+                }
+                self.read()?;
+                continue;
+            }
+
+            if let ParseKeyResult::Key(key, key_len) = parse_key(&self.buffer, self.is_eof) {
+                self.buffer.drain(..key_len);
+                return Ok(Some(key));
+            }
+
+            //
+            // Input ended in the middle of a paste
+            //
+            if self.is_eof {
+                self.buffer.clear();
+                return Ok(None);
+            }
+
+            //
+            // Give the rest of a partial escape sequence
+            // or character a moment to show up, before
+            // deciding it's all there is. Pastes are
+            // always waited on until their end.
+            //
+            if !self.wait_for_input(ESCAPE_TIMEOUT)? {
+                if let ParseKeyResult::Key(key, key_len) = parse_key(&self.buffer, true) {
+                    self.buffer.drain(..key_len);
+                    return Ok(Some(key));
+                }
+            }
+            self.read()?;
+        }
+    }
+
+    fn read(&mut self) -> Result<()> {
+        let mut buf: [u8; 1000] = [0; 1000];
+
+        match self.input.read(&mut buf) {
+            Ok(0) => self.is_eof = true,
+            Ok(bytes_read) => self.buffer.extend_from_slice(&buf[..bytes_read]),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        Ok(())
+    }
+
+    fn wait_for_input(&self, timeout: Duration) -> Result<bool> {
+        let mut poll_fds = [PollFd::new(self.input.as_raw_fd(), PollFlags::POLLIN)];
+
+        match poll(&mut poll_fds, timeout.as_millis() as i32) {
+            Ok(ready_fds) => Ok(0 < ready_fds),
+            Err(Errno::EINTR) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+impl Iterator for KeyDecoder {
+    type Item = Result<Key>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_key().transpose()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum ParseKeyResult {
+    /// Key and the number of bytes it took up
+    Key(Key, usize),
+    /// More bytes are needed to tell which key
+    Incomplete,
+}
+
+///
+/// Parse the first key from terminal input
+///
+/// `is_complete` means no more input is coming
+/// soon, so partial escape sequences are taken
+/// as they are. Pastes are incomplete until
+/// their end marker regardless.
+///
+fn parse_key(bytes: &[u8], is_complete: bool) -> ParseKeyResult {
+    let key = match bytes[0] {
+        0x1b => return parse_escape(bytes, is_complete),
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        0x7f | 0x08 => Key::Backspace,
+        0x00 => Key::Ctrl(' '),
+        b @ 0x01..=0x1a => Key::Ctrl((b'a' + b - 1) as char),
+        b @ 0x1c..=0x1f => Key::Ctrl((b'\\' + b - 0x1c) as char),
+        _ => return parse_char(bytes, is_complete, Key::Char),
+    };
+
+    ParseKeyResult::Key(key, 1)
+}
+
+fn parse_escape(bytes: &[u8], is_complete: bool) -> ParseKeyResult {
+    let key = match bytes.get(1) {
+        None if is_complete => Key::Escape,
+        None => return ParseKeyResult::Incomplete,
+        Some(b'[') => return parse_csi(bytes, is_complete),
+        Some(b'O') => return parse_ss3(bytes, is_complete),
+        Some(0x7f | 0x08) => return ParseKeyResult::Key(Key::AltBackspace, 2),
+        Some(0x20..=0x7e | 0x80..) => {
+            return match parse_char(&bytes[1..], is_complete, Key::Alt) {
+                ParseKeyResult::Key(key, key_len) => ParseKeyResult::Key(key, key_len + 1),
+                ParseKeyResult::Incomplete => ParseKeyResult::Incomplete,
+            };
+        }
+        //
+        // Escape followed by another control
+        // character, such as a second Escape
+        //
+        Some(..) => Key::Escape,
+    };
+
+    ParseKeyResult::Key(key, 1)
+}
+
+///
+/// Parse a Control Sequence Introducer sequence,
+/// `ESC [ <parameters> <final byte>`
+///
+fn parse_csi(bytes: &[u8], is_complete: bool) -> ParseKeyResult {
+    let sequence_len = match bytes[2..].iter().position(|b| (0x40..=0x7e).contains(b)) {
+        Some(final_byte_index) => 2 + final_byte_index + 1,
+        None if is_complete => return ParseKeyResult::Key(Key::Alt('['), 2),
+        None => return ParseKeyResult::Incomplete,
+    };
+    let mut parameters = str::from_utf8(&bytes[2..sequence_len - 1])
+        .unwrap_or_default()
+        .split(';');
+    let number = parameters.next().unwrap_or_default();
+    let modifier = parameters.next();
+    let is_ctrl_or_alt = matches!(modifier, Some("3" | "5"));
+
+    if let (b'~', "200") = (bytes[sequence_len - 1], number) {
+        return parse_paste(&bytes[sequence_len..], sequence_len);
+    }
+
+    let key = match bytes[sequence_len - 1] {
+        b'A' => Key::Up,
+        b'B' => Key::Down,
+        b'C' if is_ctrl_or_alt => Key::CtrlRight,
+        b'D' if is_ctrl_or_alt => Key::CtrlLeft,
+        b'C' => Key::Right,
+        b'D' => Key::Left,
+        b'H' => Key::Home,
+        b'F' => Key::End,
+        b'Z' => Key::BackTab,
+        b'~' => match number {
+            "1" | "7" => Key::Home,
+            "2" => Key::Insert,
+            "3" => Key::Delete,
+            "4" | "8" => Key::End,
+            "5" => Key::PageUp,
+            "6" => Key::PageDown,
+            "11" => Key::F(1),
+            "12" => Key::F(2),
+            "13" => Key::F(3),
+            "14" => Key::F(4),
+            "15" => Key::F(5),
+            "17" => Key::F(6),
+            "18" => Key::F(7),
+            "19" => Key::F(8),
+            "20" => Key::F(9),
+            "21" => Key::F(10),
+            "23" => Key::F(11),
+            "24" => Key::F(12),
+            _ => Key::Unknown,
+        },
+        _ => Key::Unknown,
+    };
+
+    ParseKeyResult::Key(key, sequence_len)
+}
+
+///
+/// Parse pasted text up until the paste end
+/// marker, `ESC [ 201 ~`
+///
+fn parse_paste(bytes: &[u8], paste_start_len: usize) -> ParseKeyResult {
+    const PASTE_END: &[u8] = b"\x1b[201~";
+
+    match bytes
+        .windows(PASTE_END.len())
+        .position(|window| window == PASTE_END)
+    {
+        Some(paste_len) => {
+            let paste = String::from_utf8_lossy(&bytes[..paste_len]).into_owned();
+            ParseKeyResult::Key(
+                Key::Paste(paste),
+                paste_start_len + paste_len + PASTE_END.len(),
+            )
+        }
+        None => ParseKeyResult::Incomplete,
+    }
+}
+
+///
+/// Parse a Single Shift 3 sequence, `ESC O <byte>`,
+/// which some terminals send for arrow keys and
+/// F1 to F4
+///
+fn parse_ss3(bytes: &[u8], is_complete: bool) -> ParseKeyResult {
+    let key = match bytes.get(2) {
+        None if is_complete => return ParseKeyResult::Key(Key::Alt('O'), 2),
+        None => return ParseKeyResult::Incomplete,
+        Some(b'A') => Key::Up,
+        Some(b'B') => Key::Down,
+        Some(b'C') => Key::Right,
+        Some(b'D') => Key::Left,
+        Some(b'H') => Key::Home,
+        Some(b'F') => Key::End,
+        Some(b'P') => Key::F(1),
+        Some(b'Q') => Key::F(2),
+        Some(b'R') => Key::F(3),
+        Some(b'S') => Key::F(4),
+        Some(..) => Key::Unknown,
+    };
+
+    ParseKeyResult::Key(key, 3)
+}
+
+fn parse_char(bytes: &[u8], is_complete: bool, to_key: fn(char) -> Key) -> ParseKeyResult {
+    let char_len = match bytes[0] {
+        0x00..=0x7f => 1,
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => return ParseKeyResult::Key(to_key(char::REPLACEMENT_CHARACTER), 1),
+    };
+
+    if bytes.len() < char_len {
+        return if is_complete {
+            ParseKeyResult::Key(to_key(char::REPLACEMENT_CHARACTER), bytes.len())
+        } else {
+            ParseKeyResult::Incomplete
+        };
+    }
+
+    match str::from_utf8(&bytes[..char_len]) {
+        Ok(s) => ParseKeyResult::Key(to_key(s.chars().next().unwrap_or_default()), char_len),
+        Err(..) => ParseKeyResult::Key(to_key(char::REPLACEMENT_CHARACTER), 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_key, Key, ParseKeyResult};
+
+    fn key(bytes: &[u8]) -> (Key, usize) {
+        match parse_key(bytes, true) {
+            ParseKeyResult::Key(key, key_len) => (key, key_len),
+            ParseKeyResult::Incomplete => panic!("incomplete key"),
+        }
+    }
+
+    #[test]
+    fn parses_characters() {
+        assert_eq!(key(b"ab"), (Key::Char('a'), 1));
+        assert_eq!(key("é".as_bytes()), (Key::Char('é'), 2));
+        assert_eq!(key(b"\xff"), (Key::Char(char::REPLACEMENT_CHARACTER), 1));
+    }
+
+    #[test]
+    fn parses_control_keys() {
+        assert_eq!(key(b"\r"), (Key::Enter, 1));
+        assert_eq!(key(b"\x7f"), (Key::Backspace, 1));
+        assert_eq!(key(b"\x01"), (Key::Ctrl('a'), 1));
+        assert_eq!(key(b"\x17"), (Key::Ctrl('w'), 1));
+        assert_eq!(key(b"\x1f"), (Key::Ctrl('_'), 1));
+    }
+
+    #[test]
+    fn parses_escape_sequences() {
+        assert_eq!(key(b"\x1b[Dx"), (Key::Left, 3));
+        assert_eq!(key(b"\x1bOH"), (Key::Home, 3));
+        assert_eq!(key(b"\x1b[3~"), (Key::Delete, 4));
+        assert_eq!(key(b"\x1b[1;5C"), (Key::CtrlRight, 6));
+        assert_eq!(key(b"\x1b[15~"), (Key::F(5), 5));
+        assert_eq!(key(b"\x1b[99X"), (Key::Unknown, 5));
+    }
+
+    #[test]
+    fn parses_alt_keys() {
+        assert_eq!(key(b"\x1bb"), (Key::Alt('b'), 2));
+        assert_eq!(key(b"\x1b\x7f"), (Key::AltBackspace, 2));
+        assert_eq!(key("\x1bé".as_bytes()), (Key::Alt('é'), 3));
+    }
+
+    #[test]
+    fn tells_lone_escape_from_escape_sequence() {
+        assert_eq!(parse_key(b"\x1b", false), ParseKeyResult::Incomplete);
+        assert_eq!(parse_key(b"\x1b[1;", false), ParseKeyResult::Incomplete);
+        assert_eq!(key(b"\x1b"), (Key::Escape, 1));
+        assert_eq!(key(b"\x1b\x1b[A"), (Key::Escape, 1));
+        assert_eq!(key(b"\x1b["), (Key::Alt('['), 2));
+    }
+
+    #[test]
+    fn parses_bracketed_pastes() {
+        assert_eq!(
+            key(b"\x1b[200~rg 'a\x1b[Db'\n\x1b[201~x"),
+            (Key::Paste(String::from("rg 'a\x1b[Db'\n")), 23)
+        );
+        assert_eq!(parse_key(b"\x1b[200~rg", true), ParseKeyResult::Incomplete);
+    }
+
+    #[test]
+    fn waits_for_rest_of_multibyte_characters() {
+        assert_eq!(
+            parse_key(&"é".as_bytes()[..1], false),
+            ParseKeyResult::Incomplete
+        );
+        assert_eq!(
+            key(&"é".as_bytes()[..1]),
+            (Key::Char(char::REPLACEMENT_CHARACTER), 1)
+        );
+    }
+}
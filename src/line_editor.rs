@@ -0,0 +1,239 @@
+///
+/// Single line of editable text with a cursor
+///
+/// Editing functions are named after their
+/// Readline counterparts.
+///
+#[derive(Clone, Default)]
+pub struct LineEditor {
+    text: String,
+
+    /// Byte offset into `text`, always on a
+    /// character boundary
+    cursor: usize,
+}
+
+impl LineEditor {
+    pub fn new(text: String) -> Self {
+        let cursor = text.len();
+        Self { text, cursor }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn into_text(self) -> String {
+        self.text
+    }
+
+    pub fn insert(&mut self, c: char) {
+        self.text.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+    }
+
+    pub fn insert_str(&mut self, s: &str) {
+        self.text.insert_str(self.cursor, s);
+        self.cursor += s.len();
+    }
+
+    pub fn beginning_of_line(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub fn end_of_line(&mut self) {
+        self.cursor = self.text.len();
+    }
+
+    pub fn backward_char(&mut self) {
+        self.cursor = self.previous_char_boundary();
+    }
+
+    pub fn forward_char(&mut self) {
+        self.cursor = self.next_char_boundary();
+    }
+
+    pub fn backward_word(&mut self) {
+        self.cursor = self.previous_word_boundary(is_word_char);
+    }
+
+    pub fn forward_word(&mut self) {
+        self.cursor = self.next_word_boundary(is_word_char);
+    }
+
+    pub fn backward_delete_char(&mut self) {
+        let start = self.previous_char_boundary();
+        self.delete(start, self.cursor);
+    }
+
+    pub fn delete_char(&mut self) {
+        let end = self.next_char_boundary();
+        self.delete(self.cursor, end);
+    }
+
+    /// Delete the word before the cursor, with
+    /// words delimited by whitespace
+    pub fn unix_word_rubout(&mut self) {
+        let start = self.previous_word_boundary(|c| !c.is_whitespace());
+        self.delete(start, self.cursor);
+    }
+
+    /// Delete the word before the cursor, with
+    /// words made of letters and digits
+    pub fn backward_kill_word(&mut self) {
+        let start = self.previous_word_boundary(is_word_char);
+        self.delete(start, self.cursor);
+    }
+
+    pub fn kill_word(&mut self) {
+        let end = self.next_word_boundary(is_word_char);
+        self.delete(self.cursor, end);
+    }
+
+    pub fn unix_line_discard(&mut self) {
+        self.delete(0, self.cursor);
+    }
+
+    pub fn kill_line(&mut self) {
+        self.delete(self.cursor, self.text.len());
+    }
+
+    fn delete(&mut self, start: usize, end: usize) {
+        self.text.replace_range(start..end, "");
+        self.cursor = start;
+    }
+
+    fn previous_char_boundary(&self) -> usize {
+        self.text[..self.cursor]
+            .char_indices()
+            .next_back()
+            .map_or(0, |(i, _)| i)
+    }
+
+    fn next_char_boundary(&self) -> usize {
+        self.text[self.cursor..]
+            .chars()
+            .next()
+            .map_or(self.cursor, |c| self.cursor + c.len_utf8())
+    }
+
+    /// Start of the word before the cursor,
+    /// skipping any non-word characters first
+    fn previous_word_boundary(&self, is_word_char: impl Fn(char) -> bool) -> usize {
+        let mut boundary = self.cursor;
+        let mut in_word = false;
+
+        for (i, c) in self.text[..self.cursor].char_indices().rev() {
+            if is_word_char(c) {
+                in_word = true;
+            } else if in_word {
+                break;
+            }
+            boundary = i;
+        }
+
+        boundary
+    }
+
+    /// End of the word after the cursor,
+    /// skipping any non-word characters first
+    fn next_word_boundary(&self, is_word_char: impl Fn(char) -> bool) -> usize {
+        let mut in_word = false;
+
+        for (i, c) in self.text[self.cursor..].char_indices() {
+            if is_word_char(c) {
+                in_word = true;
+            } else if in_word {
+                return self.cursor + i;
+            }
+        }
+
+        self.text.len()
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LineEditor;
+
+    fn line_editor(text: &str, cursor: usize) -> LineEditor {
+        let mut line_editor = LineEditor::new(text.to_owned());
+        line_editor.cursor = cursor;
+        line_editor
+    }
+
+    #[test]
+    fn inserts_at_cursor() {
+        let mut le = line_editor("rg TODO", 3);
+        le.insert_str("-i ");
+        le.insert('é');
+        assert_eq!((le.text(), le.cursor()), ("rg -i éTODO", 8));
+    }
+
+    #[test]
+    fn moves_by_character_over_multibyte_text() {
+        let mut le = line_editor("aé", 3);
+        le.backward_char();
+        assert_eq!(le.cursor(), 1);
+        le.backward_char();
+        le.backward_char();
+        assert_eq!(le.cursor(), 0);
+        le.forward_char();
+        le.forward_char();
+        le.forward_char();
+        assert_eq!(le.cursor(), 3);
+    }
+
+    #[test]
+    fn moves_by_word() {
+        let mut le = line_editor("rg --foo-bar  baz", 17);
+        le.backward_word();
+        assert_eq!(le.cursor(), 14);
+        le.backward_word();
+        assert_eq!(le.cursor(), 9);
+        le.forward_word();
+        assert_eq!(le.cursor(), 12);
+        le.beginning_of_line();
+        le.forward_word();
+        assert_eq!(le.cursor(), 2);
+    }
+
+    #[test]
+    fn deletes_characters() {
+        let mut le = line_editor("abc", 1);
+        le.delete_char();
+        assert_eq!((le.text(), le.cursor()), ("ac", 1));
+        le.backward_delete_char();
+        le.backward_delete_char();
+        assert_eq!((le.text(), le.cursor()), ("c", 0));
+    }
+
+    #[test]
+    fn kills_words() {
+        let mut le = line_editor("rg --foo-bar baz", 12);
+        le.backward_kill_word();
+        assert_eq!((le.text(), le.cursor()), ("rg --foo- baz", 9));
+        le.unix_word_rubout();
+        assert_eq!((le.text(), le.cursor()), ("rg  baz", 3));
+        le.kill_word();
+        assert_eq!((le.text(), le.cursor()), ("rg ", 3));
+    }
+
+    #[test]
+    fn kills_lines() {
+        let mut le = line_editor("rg TODO src", 7);
+        le.kill_line();
+        assert_eq!((le.text(), le.cursor()), ("rg TODO", 7));
+        le.backward_char();
+        le.unix_line_discard();
+        assert_eq!((le.text(), le.cursor()), ("O", 0));
+    }
+}
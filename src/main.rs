@@ -1,82 +1,110 @@
-use nix::fcntl::OFlag;
-use nix::pty::{grantpt, posix_openpt, ptsname, unlockpt};
+use nix::sys::pthread::pthread_kill;
+use nix::sys::signal::Signal;
 use nix::sys::termios;
 use nix::unistd::isatty;
 use std::fs::File;
-use std::io::{stdin, stdout};
-use std::os::fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd};
-use std::sync::mpsc;
+use std::io::{stdin, stdout, Read, Write};
+use std::os::fd::{AsFd, AsRawFd};
+use std::os::unix::thread::JoinHandleExt;
+use std::process::{ExitCode, Stdio};
+use std::sync::{mpsc, Arc};
 
+mod command;
 mod error;
+mod history;
+mod init;
+mod keys;
+mod line_editor;
+mod options;
+mod output_limit;
+mod preview;
+mod prompt;
+mod pty;
+mod renderer;
 mod result;
+mod run_status;
+mod screen;
 mod threads;
+mod tokenize;
 
+use crate::command::{build_command, write_piped_input, BuildCommandResult};
+use crate::options::{parse_options, Print, PrintDestination};
 use crate::result::Result;
 use crate::threads::{
-    command_exit_thread, command_output_thread, user_input_thread, user_interface_thread,
-    CommandExitEvent, CommandOutputEvent, UserInputEvent, UserInterfaceEvent,
+    command_exit_thread, command_output_thread, resize_signals, terminal_resize_thread,
+    user_input_thread, user_interface_thread, CommandExitEvent, CommandOutputEvent, UserInputEvent,
+    UserInterfaceEvent,
 };
 
-fn main() -> Result<()> {
+fn main() -> Result<ExitCode> {
+    //
+    // Argument processing
+    //
+    let options = parse_options()?;
+
     //
     // Input processing
     //
-    let stdin = stdin();
+    let mut stdin = stdin();
     let stdout = stdout();
 
-    let stdin_fd = stdin.as_raw_fd();
-    let stdout_fd = stdout.as_raw_fd();
+    //
+    // Pipe mode
+    //
+    // Capture everything piped into pvw up front,
+    // so it can be replayed into every command.
+    // Keystrokes then come from the terminal
+    // itself rather than stdin.
+    //
+    let is_stdin_tty = isatty(stdin.as_raw_fd())?;
+    let (tty, piped_input): (File, Option<Arc<[u8]>>) = if is_stdin_tty {
+        (stdin.as_fd().try_clone_to_owned()?.into(), None)
+    } else {
+        let mut piped_input = Vec::new();
+        stdin.read_to_end(&mut piped_input)?;
+
+        let tty = File::options().read(true).write(true).open("/dev/tty")?;
+        (tty, Some(piped_input.into()))
+    };
+    let tty_fd = tty.as_raw_fd();
 
-    let is_stdin_tty = isatty(stdin_fd)?;
-    if !is_stdin_tty {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidInput,
-            "stdin needs to be a tty",
-        )
-        .into());
-    }
-    let is_stdout_tty = isatty(stdout_fd)?;
-    if !is_stdout_tty {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidInput,
-            "stdout needs to be a tty",
-        )
-        .into());
-    }
+    //
+    // Output processing
+    //
+    // Draw on the terminal itself when stdout is
+    // piped elsewhere, leaving stdout for the
+    // output of the accepted command
+    //
+    let is_stdout_tty = isatty(stdout.as_raw_fd())?;
+    let terminal: File = if is_stdout_tty {
+        stdout.as_fd().try_clone_to_owned()?.into()
+    } else {
+        File::options().read(true).write(true).open("/dev/tty")?
+    };
+    let terminal_fd = terminal.as_raw_fd();
 
     //
     // Terminal configuration
     //
-    let mut term_config = termios::tcgetattr(stdout_fd)?;
+    let mut term_config = termios::tcgetattr(terminal_fd)?;
     let term_config_original = term_config.clone();
     termios::cfmakeraw(&mut term_config);
-    termios::tcsetattr(stdout_fd, termios::SetArg::TCSANOW, &term_config)?;
+    termios::tcsetattr(terminal_fd, termios::SetArg::TCSANOW, &term_config)?;
 
-    let mut term_config = termios::tcgetattr(stdin_fd)?;
+    let mut term_config = termios::tcgetattr(tty_fd)?;
+    let tty_config_original = term_config.clone();
     termios::cfmakeraw(&mut term_config);
-    termios::tcsetattr(stdin_fd, termios::SetArg::TCSANOW, &term_config)?;
-
-    //
-    // Pseudoterminal configuration
-    //
-    let pty_master = posix_openpt(OFlag::O_RDWR | OFlag::O_NOCTTY)?;
-
-    grantpt(&pty_master)?;
-    unlockpt(&pty_master)?;
-
-    let pty_slave_path = unsafe { ptsname(&pty_master)? };
-    let pty_slave_fd: OwnedFd = File::options()
-        .read(true)
-        .write(true)
-        .open(pty_slave_path)?
-        .into();
-
-    let pty_master_1 = unsafe { File::from_raw_fd(pty_master.into_raw_fd()) };
-    let pty_master_2 = pty_master_1.try_clone()?;
+    termios::tcsetattr(tty_fd, termios::SetArg::TCSANOW, &term_config)?;
 
     //
     // Threads
     //
+    // Resize signals are left to the terminal
+    // resize thread, so block them everywhere
+    // else. Spawned threads inherit the mask.
+    //
+    resize_signals().thread_block()?;
+
     let (command_exit_events_sender, command_exit_events_receiver) =
         mpsc::channel::<CommandExitEvent>();
     let (command_output_events_sender, command_output_events_receiver) =
@@ -90,30 +118,45 @@ fn main() -> Result<()> {
         user_input_events_sender.clone(),
         user_interface_events_sender.clone(),
         command_exit_events_receiver,
+        options.kill_signal,
+        options.kill_grace,
+        options.timeout,
+    );
+    let terminal_resize_thread_handle = terminal_resize_thread(
+        command_exit_events_sender.clone(),
+        command_output_events_sender.clone(),
+        user_input_events_sender.clone(),
+        user_interface_events_sender.clone(),
+        terminal.try_clone()?,
     );
     let command_output_thread_handle = command_output_thread(
         command_exit_events_sender.clone(),
         user_input_events_sender.clone(),
         user_interface_events_sender.clone(),
         command_output_events_receiver,
-        pty_master_1,
+        options.max_output_bytes,
+        options.max_output_lines,
     );
     let user_input_thread_handle = user_input_thread(
         command_exit_events_sender.clone(),
         command_output_events_sender.clone(),
         user_interface_events_sender,
         user_input_events_receiver,
-        pty_master_2,
-        pty_slave_fd,
-        stdin,
+        tty.try_clone()?,
+        terminal.try_clone()?,
+        piped_input.clone(),
+        options.command_text.clone(),
+        options.shell.clone(),
+        options.debounce,
     );
     let user_interface_thread_handle = user_interface_thread(
         command_exit_events_sender,
         command_output_events_sender,
         user_input_events_sender,
         user_interface_events_receiver,
-        stdout,
-        term_config_original,
+        terminal.try_clone()?,
+        options.command_text,
+        options.sticky,
     );
 
     let command_exit_thread_result = command_exit_thread_handle.join()?;
@@ -121,10 +164,83 @@ fn main() -> Result<()> {
     let user_input_thread_result = user_input_thread_handle.join()?;
     let user_interface_thread_result = user_interface_thread_handle.join()?;
 
+    //
+    // Wake the terminal resize thread, which then
+    // finds the other threads gone. Errors mean
+    // it already stopped.
+    //
+    let _ = pthread_kill(
+        terminal_resize_thread_handle.as_pthread_t(),
+        Signal::SIGWINCH,
+    );
+    let terminal_resize_thread_result = terminal_resize_thread_handle.join()?;
+
+    termios::tcsetattr(tty_fd, termios::SetArg::TCSANOW, &tty_config_original)?;
+    termios::tcsetattr(terminal_fd, termios::SetArg::TCSANOW, &term_config_original)?;
+
     command_exit_thread_result?;
     command_output_thread_result?;
-    user_input_thread_result?;
+    let accepted_command_text = user_input_thread_result?;
     user_interface_thread_result?;
+    terminal_resize_thread_result?;
+
+    //
+    // Cancelled, such as with Escape
+    //
+    let command_text = match accepted_command_text {
+        Some(command_text) => command_text,
+        None => return Ok(ExitCode::from(130)),
+    };
+
+    let print = match options.print {
+        Some(print) => print,
+        None if is_stdout_tty => Print::Command,
+        None => Print::Output,
+    };
+    let destination: File = match options.print_destination {
+        PrintDestination::Stdout => stdout.as_fd().try_clone_to_owned()?.into(),
+        PrintDestination::Fd(fd) => fd.into(),
+        PrintDestination::File(path) => File::create(path)?,
+    };
+
+    match print {
+        Print::Command => writeln!(&destination, "{}", command_text)?,
+        //
+        // Run the accepted command once more, now
+        // that the preview is done with the terminal
+        //
+        Print::Output => write_command_output(
+            options.shell.as_deref(),
+            &command_text,
+            piped_input,
+            destination,
+        )?,
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn write_command_output(
+    shell: Option<&str>,
+    command_text: &str,
+    piped_input: Option<Arc<[u8]>>,
+    destination: File,
+) -> Result<()> {
+    let mut command = match build_command(shell, command_text) {
+        BuildCommandResult::Ready(command) => command,
+        BuildCommandResult::Empty | BuildCommandResult::Incomplete => return Ok(()),
+    };
+
+    command.stdout(destination);
+    if piped_input.is_some() {
+        command.stdin(Stdio::piped());
+    }
+
+    let mut command_process = command.spawn()?;
+    if let Some(piped_input) = piped_input {
+        write_piped_input(&mut command_process, &piped_input);
+    }
+    command_process.wait()?;
 
     Ok(())
 }
@@ -0,0 +1,357 @@
+use nix::fcntl::{fcntl, FcntlArg};
+use nix::sys::signal::Signal;
+use std::env;
+use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::init::init_script;
+use crate::result::Result;
+use crate::tokenize::join_quoted;
+
+const USAGE: &str = "\
+Usage: pvw [OPTIONS] [COMMAND [ARGS...]]
+       pvw init <bash|zsh|fish>
+
+Live preview for command-line tools.
+
+Arguments:
+  [COMMAND [ARGS...]]  Initial command to preview. A single argument is
+                       taken as the whole command line, as typed
+
+Commands:
+  init <bash|zsh|fish>  Print key bindings to open pvw on the command
+                        being typed, with Ctrl-X Ctrl-P
+
+Options:
+  -s, --shell[=SHELL]  Run commands with `SHELL -c`, allowing pipes,
+                       quotes, globs and variables. Several arguments
+                       are joined with spaces, as the shell would see
+                       them typed. Defaults to $SHELL
+  -p, --print <WHAT>   What to print when a command is accepted with
+                       Enter: `command` for the command text or
+                       `output` for its output. Defaults to `output`
+                       when stdout is piped, otherwise `command`
+      --print-fd <FD>  Print to file descriptor FD instead of stdout
+      --print-file <PATH>
+                       Print to PATH instead of stdout
+  -d, --debounce <MS>  Wait until typing pauses for MS milliseconds
+                       before running the command. Defaults to 100
+      --kill-signal <SIGNAL>
+                       Signal to stop a command with once it's
+                       replaced, such as INT or TERM. Defaults to TERM
+      --kill-grace <MS>
+                       How long a command has to exit after the signal
+                       before it's killed with KILL. Defaults to 500
+  -t, --timeout <MS>   Stop commands still running after MS milliseconds,
+                       keeping their output so far
+      --max-lines <LINES>
+                       Stop commands once they've output LINES lines,
+                       or 0 for no limit. Defaults to 100000
+      --max-bytes <BYTES>
+                       Stop commands once they've output BYTES bytes,
+                       or 0 for no limit. Defaults to 10485760 (10 MiB)
+      --sticky[=UNTIL] Keep showing the last successful output, dimmed,
+                       until the next run has output (`output`) or
+                       succeeds (`success`). Defaults to `output`
+  -h, --help           Print help
+  -V, --version        Print version
+
+Exit status is 0 when a command is accepted with Enter, and 130 when
+cancelled with Escape.
+";
+
+pub struct Options {
+    /// Initial command text, pre-filled into the
+    /// prompt and previewed immediately
+    pub command_text: String,
+
+    /// Shell to run commands with, if any. Otherwise
+    /// commands are split into a program and its
+    /// arguments and run directly.
+    pub shell: Option<String>,
+
+    /// What to print when a command is accepted,
+    /// if set explicitly
+    pub print: Option<Print>,
+
+    /// Where to print it
+    pub print_destination: PrintDestination,
+
+    /// How long typing has to pause for before
+    /// the command is run again
+    pub debounce: Duration,
+
+    /// Signal to ask commands to exit with
+    pub kill_signal: Signal,
+
+    /// How long commands have to exit before
+    /// being killed outright
+    pub kill_grace: Duration,
+
+    /// How long commands get to run, if limited
+    pub timeout: Option<Duration>,
+
+    /// How much output runs get to write, if
+    /// limited
+    pub max_output_lines: Option<usize>,
+    pub max_output_bytes: Option<usize>,
+
+    /// Whether to keep earlier output on screen
+    /// while the next run gets going, and until
+    /// when
+    pub sticky: Option<Sticky>,
+}
+
+pub enum Print {
+    Command,
+    Output,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sticky {
+    UntilOutput,
+    UntilSuccess,
+}
+
+pub enum PrintDestination {
+    Stdout,
+    Fd(OwnedFd),
+    File(PathBuf),
+}
+
+pub fn parse_options() -> Result<Options> {
+    use lexopt::prelude::*;
+
+    let mut command_args: Vec<String> = Vec::new();
+    let mut shell: Option<String> = None;
+    let mut print: Option<Print> = None;
+    let mut print_destination = PrintDestination::Stdout;
+    let mut debounce = Duration::from_millis(100);
+    let mut kill_signal = Signal::SIGTERM;
+    let mut kill_grace = Duration::from_millis(500);
+    let mut timeout: Option<Duration> = None;
+    let mut max_output_lines: Option<usize> = Some(100_000);
+    let mut max_output_bytes: Option<usize> = Some(10 * 1024 * 1024);
+    let mut sticky: Option<Sticky> = None;
+    let mut parser = lexopt::Parser::from_env();
+
+    while let Some(arg) = parser.next()? {
+        match arg {
+            Short('h') | Long("help") => {
+                print!("{}", USAGE);
+                process::exit(0);
+            }
+            Short('s') | Long("shell") => {
+                shell = match parser.optional_value() {
+                    Some(shell) => Some(shell.string()?),
+                    None => Some(default_shell()),
+                };
+            }
+            Short('p') | Long("print") => {
+                print = match parser.value()?.string()?.as_str() {
+                    "command" => Some(Print::Command),
+                    "output" => Some(Print::Output),
+                    value => {
+                        return Err(lexopt::Error::from(format!(
+                            "invalid value '{}' for '--print', expected 'command' or 'output'",
+                            value
+                        ))
+                        .into())
+                    }
+                };
+            }
+            Long("print-fd") => {
+                print_destination = PrintDestination::Fd(parse_fd(parser.value()?.parse()?)?);
+            }
+            Long("print-file") => {
+                print_destination = PrintDestination::File(parser.value()?.into());
+            }
+            Short('d') | Long("debounce") => {
+                debounce = Duration::from_millis(parser.value()?.parse()?);
+            }
+            Long("kill-signal") => {
+                let value = parser.value()?.string()?;
+                kill_signal = parse_signal(&value).ok_or_else(|| {
+                    lexopt::Error::from(format!(
+                        "invalid value '{}' for '--kill-signal', expected a signal name",
+                        value
+                    ))
+                })?;
+            }
+            Long("kill-grace") => {
+                kill_grace = Duration::from_millis(parser.value()?.parse()?);
+            }
+            Short('t') | Long("timeout") => {
+                timeout = Some(Duration::from_millis(parser.value()?.parse()?));
+            }
+            Long("max-lines") => {
+                max_output_lines = Some(parser.value()?.parse()?).filter(|&lines| 0 < lines);
+            }
+            Long("max-bytes") => {
+                max_output_bytes = Some(parser.value()?.parse()?).filter(|&bytes| 0 < bytes);
+            }
+            Long("sticky") => {
+                let value = match parser.optional_value() {
+                    Some(value) => value.string()?,
+                    None => String::from("output"),
+                };
+                sticky = match value.as_str() {
+                    "output" => Some(Sticky::UntilOutput),
+                    "success" => Some(Sticky::UntilSuccess),
+                    value => {
+                        return Err(lexopt::Error::from(format!(
+                            "invalid value '{}' for '--sticky', expected 'output' or 'success'",
+                            value
+                        ))
+                        .into())
+                    }
+                };
+            }
+            Short('V') | Long("version") => {
+                println!("pvw {}", env!("CARGO_PKG_VERSION"));
+                process::exit(0);
+            }
+            //
+            // Everything from the first positional
+            // argument onwards belongs to the command,
+            // including anything that looks like an
+            // option. `pvw rg -i todo` should preview
+            // `rg -i todo`.
+            //
+            Value(program) => {
+                command_args.push(program.string()?);
+                for arg in parser.raw_args()? {
+                    command_args.push(arg.string()?);
+                }
+            }
+            _ => return Err(arg.unexpected().into()),
+        }
+    }
+
+    if let [subcommand, init_args @ ..] = command_args.as_slice() {
+        if subcommand == "init" {
+            match init_args {
+                [shell] => match init_script(shell) {
+                    Some(script) => {
+                        print!("{}", script);
+                        process::exit(0);
+                    }
+                    None => {
+                        return Err(lexopt::Error::from(format!(
+                            "unsupported shell '{}' for 'init', expected bash, fish or zsh",
+                            shell
+                        ))
+                        .into())
+                    }
+                },
+                _ => return Err(lexopt::Error::from("expected 'pvw init <bash|fish|zsh>'").into()),
+            }
+        }
+    }
+
+    Ok(Options {
+        command_text: command_text(&command_args, shell.is_some()),
+        shell,
+        print,
+        print_destination,
+        debounce,
+        kill_signal,
+        kill_grace,
+        timeout,
+        max_output_lines,
+        max_output_bytes,
+        sticky,
+    })
+}
+
+///
+/// Signal by name, with or without the `SIG`
+/// prefix, such as `TERM` or `sigint`
+///
+fn parse_signal(name: &str) -> Option<Signal> {
+    let name = name.to_uppercase();
+    let name = format!("SIG{}", name.strip_prefix("SIG").unwrap_or(&name));
+    Signal::from_str(&name).ok()
+}
+
+///
+/// Duplicate of an inherited file descriptor,
+/// taken before any file gets opened which
+/// could be mistaken for it
+///
+fn parse_fd(fd: RawFd) -> Result<OwnedFd> {
+    match fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(0)) {
+        // The duplicate is new, and owned by no one else
+        Ok(duplicate) => Ok(unsafe { OwnedFd::from_raw_fd(duplicate) }),
+        Err(err) => Err(lexopt::Error::from(format!(
+            "invalid value '{}' for '--print-fd': {}",
+            fd,
+            err.desc()
+        ))
+        .into()),
+    }
+}
+
+///
+/// Command line for the arguments, where one
+/// argument is taken as typed, like a prompt
+/// passed by the shell key bindings, while
+/// several are quoted to keep them apart,
+/// unless they're for the shell to interpret
+///
+fn command_text(command_args: &[String], is_shell: bool) -> String {
+    match command_args {
+        [command_text] => command_text.clone(),
+        _ if is_shell => command_args.join(" "),
+        _ => join_quoted(command_args),
+    }
+}
+
+fn default_shell() -> String {
+    match env::var("SHELL") {
+        Ok(shell) if !shell.is_empty() => shell,
+        _ => String::from("/bin/sh"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::command_text;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|&arg| String::from(arg)).collect()
+    }
+
+    #[test]
+    fn takes_single_argument_as_typed() {
+        assert_eq!(
+            command_text(&args(&["echo foo bar"]), false),
+            "echo foo bar"
+        );
+        assert_eq!(
+            command_text(&args(&["rg 'a b' | wc -l"]), true),
+            "rg 'a b' | wc -l"
+        );
+        assert_eq!(command_text(&args(&[""]), false), "");
+        assert_eq!(command_text(&args(&[]), false), "");
+    }
+
+    #[test]
+    fn quotes_several_arguments() {
+        assert_eq!(
+            command_text(&args(&["printf", "%s|", "a b", "c"]), false),
+            "printf '%s|' 'a b' c"
+        );
+    }
+
+    #[test]
+    fn leaves_several_arguments_to_the_shell() {
+        assert_eq!(
+            command_text(&args(&["echo", "$HOME", "|", "wc", "-c"]), true),
+            "echo $HOME | wc -c"
+        );
+    }
+}
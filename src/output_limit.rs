@@ -0,0 +1,109 @@
+///
+/// Cap on how much output a run gets to write,
+/// in bytes and in lines
+///
+pub struct OutputLimit {
+    max_bytes: Option<usize>,
+    max_lines: Option<usize>,
+    bytes: usize,
+    lines: usize,
+    is_reached: bool,
+}
+
+impl OutputLimit {
+    pub fn new(max_bytes: Option<usize>, max_lines: Option<usize>) -> Self {
+        Self {
+            max_bytes,
+            max_lines,
+            bytes: 0,
+            lines: 0,
+            is_reached: false,
+        }
+    }
+
+    ///
+    /// Start counting over, for the next run
+    ///
+    pub fn reset(&mut self) {
+        self.bytes = 0;
+        self.lines = 0;
+        self.is_reached = false;
+    }
+
+    ///
+    /// Whether output had to be cut off
+    ///
+    pub fn is_reached(&self) -> bool {
+        self.is_reached
+    }
+
+    ///
+    /// Count the output against the limit,
+    /// returning how many of its bytes fit
+    ///
+    pub fn take(&mut self, output: &[u8]) -> usize {
+        let mut output_len = output.len();
+        if let Some(max_bytes) = self.max_bytes {
+            output_len = output_len.min(max_bytes.saturating_sub(self.bytes));
+        }
+
+        //
+        // Keep up to the end of the last line which
+        // fits, leaving out anything after it
+        //
+        if let Some(max_lines) = self.max_lines {
+            let mut line_ends = output[..output_len]
+                .iter()
+                .enumerate()
+                .filter(|(_, byte)| **byte == b'\n')
+                .map(|(index, _)| index + 1);
+            output_len = match max_lines.saturating_sub(self.lines) {
+                0 => 0,
+                lines_left => line_ends.nth(lines_left - 1).unwrap_or(output_len),
+            };
+        }
+
+        self.bytes += output_len;
+        self.lines += output[..output_len]
+            .iter()
+            .filter(|byte| **byte == b'\n')
+            .count();
+        self.is_reached |= output_len < output.len();
+
+        output_len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OutputLimit;
+
+    #[test]
+    fn cuts_off_at_max_bytes() {
+        let mut output_limit = OutputLimit::new(Some(5), None);
+        assert_eq!(output_limit.take(b"abc"), 3);
+        assert_eq!(output_limit.take(b"defg"), 2);
+        assert!(output_limit.is_reached());
+
+        output_limit.reset();
+        assert_eq!(output_limit.take(b"abcde"), 5);
+        assert!(!output_limit.is_reached());
+    }
+
+    #[test]
+    fn cuts_off_after_max_lines() {
+        let mut output_limit = OutputLimit::new(None, Some(2));
+        assert_eq!(output_limit.take(b"a\nb"), 3);
+        assert_eq!(output_limit.take(b"c\nd\n"), 2);
+        assert!(output_limit.is_reached());
+    }
+
+    #[test]
+    fn keeps_output_which_ends_on_max_lines() {
+        let mut output_limit = OutputLimit::new(None, Some(2));
+        assert_eq!(output_limit.take(b"a\nb\n"), 4);
+        assert!(!output_limit.is_reached());
+        assert_eq!(output_limit.take(b"c"), 0);
+        assert!(output_limit.is_reached());
+    }
+}
@@ -0,0 +1,313 @@
+use std::fs::File;
+use std::time::Instant;
+use unicode_width::UnicodeWidthChar;
+
+use crate::command::CommandStatus;
+use crate::options::Sticky;
+use crate::pty::terminal_size;
+use crate::result::Result;
+use crate::run_status::{format_lines, RunStatus};
+use crate::screen::{Screen, VirtualTerminal};
+
+///
+/// First row of the preview, below the prompt
+/// and status line
+///
+pub const PREVIEW_ROW: usize = 3;
+
+///
+/// Rows and columns the preview gets on the
+/// terminal, which commands run with as their
+/// window size
+///
+pub fn preview_size(terminal: &File) -> Result<(usize, usize)> {
+    let (rows, cols) = terminal_size(terminal)?;
+    Ok(((rows + 1).saturating_sub(PREVIEW_ROW).max(1), cols))
+}
+
+///
+/// Output of the latest run, or of an earlier
+/// one kept on screen in its place
+///
+pub struct Preview {
+    sticky: Option<Sticky>,
+    rows: usize,
+    cols: usize,
+    run_id: u64,
+    run_status: Option<RunStatus>,
+    virtual_terminal: VirtualTerminal,
+
+    /// Screen of the last run which succeeded
+    kept_screen: Option<Screen>,
+
+    /// Whether the kept screen is shown, as the
+    /// latest run has nothing worth showing yet
+    is_showing_kept_screen: bool,
+}
+
+impl Preview {
+    pub fn new(sticky: Option<Sticky>, rows: usize, cols: usize) -> Self {
+        Self {
+            sticky,
+            rows,
+            cols,
+            run_id: 0,
+            run_status: None,
+            virtual_terminal: VirtualTerminal::new(rows, cols),
+            kept_screen: None,
+            is_showing_kept_screen: false,
+        }
+    }
+
+    ///
+    /// Whether events from the given run are
+    /// about the latest run
+    ///
+    pub fn is_latest_run(&self, run_id: u64) -> bool {
+        run_id == self.run_id
+    }
+
+    pub fn run_status(&self) -> Option<&RunStatus> {
+        self.run_status.as_ref()
+    }
+
+    ///
+    /// Describe the run in a line cut off at the
+    /// terminal's width, as anything past it would
+    /// wrap onto the preview
+    ///
+    pub fn status_line(&self, now: Instant) -> Option<String> {
+        let mut status_line = self.run_status.as_ref()?.describe(now);
+        if self.is_stale() {
+            status_line.push_str(" · showing previous output");
+        }
+
+        let mut width = 0;
+        let end = status_line
+            .char_indices()
+            .find(|(_, c)| {
+                width += c.width().unwrap_or(0);
+                width > self.cols
+            })
+            .map(|(index, _)| index);
+        if let Some(end) = end {
+            status_line.truncate(end);
+        }
+
+        Some(status_line)
+    }
+
+    pub fn shown_screen(&self) -> &Screen {
+        match &self.kept_screen {
+            Some(kept_screen) if self.is_showing_kept_screen => kept_screen,
+            _ => self.virtual_terminal.screen(),
+        }
+    }
+
+    ///
+    /// Whether the output shown is from an
+    /// earlier run
+    ///
+    pub fn is_stale(&self) -> bool {
+        self.is_showing_kept_screen
+    }
+
+    pub fn start(&mut self, run_id: u64, now: Instant) {
+        self.run_id = run_id;
+        self.run_status = Some(RunStatus::new(now));
+        self.virtual_terminal = VirtualTerminal::new(self.rows, self.cols);
+        self.is_showing_kept_screen = self.sticky.is_some() && self.kept_screen.is_some();
+    }
+
+    ///
+    /// Returns whether the output shown changed
+    ///
+    pub fn add_output(&mut self, output: &[u8]) -> bool {
+        self.virtual_terminal.process(output);
+        if let Some(run_status) = &mut self.run_status {
+            run_status.add_output(output);
+        }
+
+        if let Some(Sticky::UntilOutput) = self.sticky {
+            self.is_showing_kept_screen = false;
+        }
+        !self.is_showing_kept_screen
+    }
+
+    ///
+    /// Returns whether the output shown changed
+    ///
+    pub fn set_command_status(&mut self, command_status: CommandStatus, now: Instant) -> bool {
+        if let Some(run_status) = &mut self.run_status {
+            run_status.set_command_status(command_status, now);
+        }
+        if !command_status.is_exit() {
+            return false;
+        }
+
+        if command_status.is_success() {
+            self.kept_screen = Some(self.virtual_terminal.screen().clone());
+        }
+
+        let was_showing_kept_screen = self.is_showing_kept_screen;
+        if command_status.is_success() || self.sticky != Some(Sticky::UntilSuccess) {
+            self.is_showing_kept_screen = false;
+        }
+        was_showing_kept_screen != self.is_showing_kept_screen
+    }
+
+    pub fn resize(&mut self, rows: usize, cols: usize) {
+        self.rows = rows;
+        self.cols = cols;
+        self.virtual_terminal.resize(rows, cols);
+        if let Some(kept_screen) = &mut self.kept_screen {
+            kept_screen.resize(rows, cols);
+        }
+    }
+
+    pub fn set_timed_out(&mut self) {
+        if let Some(run_status) = &mut self.run_status {
+            run_status.set_timed_out();
+        }
+    }
+
+    ///
+    /// Mark where output was cut off, in reverse
+    /// video on a line of its own
+    ///
+    pub fn set_truncated(&mut self) {
+        let run_status = match &mut self.run_status {
+            Some(run_status) => run_status,
+            None => return,
+        };
+        run_status.set_truncated();
+
+        let line_break = match self.virtual_terminal.screen().cursor_col() {
+            0 => "",
+            _ => "\r\n",
+        };
+        let marker = format!(
+            "\u{1b}[0m{}\u{1b}[7m truncated after {} \u{1b}[0m",
+            line_break,
+            format_lines(run_status.output_lines())
+        );
+        self.virtual_terminal.process(marker.as_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Preview;
+    use crate::command::CommandStatus;
+    use crate::options::Sticky;
+    use std::time::Instant;
+
+    fn preview_after_success(sticky: Option<Sticky>) -> Preview {
+        let mut preview = Preview::new(sticky, 4, 20);
+        preview.start(1, Instant::now());
+        preview.add_output(b"good\n");
+        preview.set_command_status(CommandStatus::Exited(0), Instant::now());
+        preview
+    }
+
+    #[test]
+    fn replaces_output_straight_away_without_sticky() {
+        let mut preview = preview_after_success(None);
+        preview.start(2, Instant::now());
+        assert_eq!(
+            (
+                preview.shown_screen().contents().as_str(),
+                preview.is_stale()
+            ),
+            ("", false)
+        );
+    }
+
+    #[test]
+    fn keeps_output_until_new_output() {
+        let mut preview = preview_after_success(Some(Sticky::UntilOutput));
+        preview.start(2, Instant::now());
+        assert_eq!(
+            (
+                preview.shown_screen().contents().as_str(),
+                preview.is_stale()
+            ),
+            ("good", true)
+        );
+
+        assert!(preview.add_output(b"new\n"));
+        assert_eq!(
+            (
+                preview.shown_screen().contents().as_str(),
+                preview.is_stale()
+            ),
+            ("new", false)
+        );
+    }
+
+    #[test]
+    fn keeps_output_until_run_finishes_without_output() {
+        let mut preview = preview_after_success(Some(Sticky::UntilOutput));
+        preview.start(2, Instant::now());
+
+        assert!(preview.set_command_status(CommandStatus::Exited(1), Instant::now()));
+        assert_eq!(
+            (
+                preview.shown_screen().contents().as_str(),
+                preview.is_stale()
+            ),
+            ("", false)
+        );
+    }
+
+    #[test]
+    fn cuts_off_status_line_at_terminal_width() {
+        let now = Instant::now();
+        let mut preview = Preview::new(Some(Sticky::UntilOutput), 4, 20);
+        preview.start(1, now);
+        preview.set_command_status(CommandStatus::Exited(0), now);
+        preview.start(2, now);
+        assert_eq!(
+            preview.status_line(now).as_deref(),
+            Some("running · 0.0s · 0 l")
+        );
+    }
+
+    #[test]
+    fn marks_truncated_output() {
+        let mut preview = Preview::new(None, 4, 30);
+        preview.start(1, Instant::now());
+        preview.add_output(b"a\r\nb");
+        preview.set_truncated();
+        assert_eq!(
+            preview.shown_screen().contents(),
+            "a\nb\n truncated after 1 line"
+        );
+    }
+
+    #[test]
+    fn keeps_output_until_success() {
+        let mut preview = preview_after_success(Some(Sticky::UntilSuccess));
+        preview.start(2, Instant::now());
+        assert!(!preview.add_output(b"error\n"));
+        assert!(!preview.set_command_status(CommandStatus::Exited(2), Instant::now()));
+        assert_eq!(
+            (
+                preview.shown_screen().contents().as_str(),
+                preview.is_stale()
+            ),
+            ("good", true)
+        );
+
+        preview.start(3, Instant::now());
+        assert!(!preview.add_output(b"better\n"));
+        assert!(preview.set_command_status(CommandStatus::Exited(0), Instant::now()));
+        assert_eq!(
+            (
+                preview.shown_screen().contents().as_str(),
+                preview.is_stale()
+            ),
+            ("better", false)
+        );
+    }
+}
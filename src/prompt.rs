@@ -0,0 +1,316 @@
+use crate::history::History;
+use crate::keys::Key;
+use crate::line_editor::LineEditor;
+
+///
+/// Command line being typed, along with its
+/// history
+///
+pub struct Prompt {
+    line_editor: LineEditor,
+    history: History,
+
+    /// Index into history entries, with the
+    /// number of entries meaning the line being
+    /// typed rather than an entry
+    history_position: usize,
+
+    /// Line being typed, put aside while
+    /// browsing history
+    history_draft: Option<LineEditor>,
+
+    history_search: Option<HistorySearch>,
+}
+
+#[derive(Clone)]
+pub struct HistorySearch {
+    pub query: String,
+    pub is_failing: bool,
+
+    /// Index of the history entry matched
+    match_position: Option<usize>,
+
+    /// Line to go back to if the search is cancelled
+    line_editor_original: LineEditor,
+}
+
+pub enum PromptResult {
+    Continue,
+    Accept,
+    Cancel,
+}
+
+enum PromptAction {
+    Insert(char),
+    Paste(String),
+    Edit(fn(&mut LineEditor)),
+    PreviousHistory,
+    NextHistory,
+    ReverseSearchHistory,
+    Accept,
+    Cancel,
+    Ignore,
+}
+
+impl Prompt {
+    pub fn new(command_text: String, history: History) -> Self {
+        let history_position = history.entries().len();
+
+        Self {
+            line_editor: LineEditor::new(command_text),
+            history,
+            history_position,
+            history_draft: None,
+            history_search: None,
+        }
+    }
+
+    pub fn line_editor(&self) -> &LineEditor {
+        &self.line_editor
+    }
+
+    pub fn history_search(&self) -> Option<&HistorySearch> {
+        self.history_search.as_ref()
+    }
+
+    ///
+    /// Save the command line to history, and hand
+    /// it over
+    ///
+    pub fn accept(mut self) -> String {
+        self.history.add(self.line_editor.text());
+        self.line_editor.into_text()
+    }
+
+    pub fn handle_key(&mut self, key: Key) -> PromptResult {
+        let key = match self.history_search {
+            Some(..) => match self.handle_history_search_key(key) {
+                Some(key) => key,
+                None => return PromptResult::Continue,
+            },
+            None => key,
+        };
+
+        match key_action(key) {
+            PromptAction::Accept => return PromptResult::Accept,
+            PromptAction::Cancel => return PromptResult::Cancel,
+            PromptAction::Ignore => {}
+            PromptAction::Insert(c) => self.line_editor.insert(c),
+            PromptAction::Paste(text) => self.line_editor.insert_str(&paste_to_line(&text)),
+            PromptAction::Edit(edit) => edit(&mut self.line_editor),
+            PromptAction::PreviousHistory => self.previous_history(),
+            PromptAction::NextHistory => self.next_history(),
+            PromptAction::ReverseSearchHistory => {
+                self.history_search = Some(HistorySearch {
+                    query: String::new(),
+                    is_failing: false,
+                    match_position: None,
+                    line_editor_original: self.line_editor.clone(),
+                });
+            }
+        }
+
+        PromptResult::Continue
+    }
+
+    fn previous_history(&mut self) {
+        if self.history_position == 0 {
+            return;
+        }
+        if self.history_position == self.history.entries().len() {
+            self.history_draft = Some(self.line_editor.clone());
+        }
+
+        self.history_position -= 1;
+        self.line_editor = LineEditor::new(self.history.entries()[self.history_position].clone());
+    }
+
+    fn next_history(&mut self) {
+        let history_len = self.history.entries().len();
+        if history_len <= self.history_position {
+            return;
+        }
+
+        self.history_position += 1;
+        self.line_editor = if self.history_position == history_len {
+            self.history_draft.take().unwrap_or_default()
+        } else {
+            LineEditor::new(self.history.entries()[self.history_position].clone())
+        };
+    }
+
+    ///
+    /// Handle a key during an incremental search,
+    /// like Readline's `reverse-search-history`
+    ///
+    /// Returns keys which end the search, and
+    /// should then be handled as usual.
+    ///
+    fn handle_history_search_key(&mut self, key: Key) -> Option<Key> {
+        let search = self.history_search.as_mut()?;
+        let history_len = self.history.entries().len();
+
+        let before = match key {
+            Key::Char(c) => {
+                search.query.push(c);
+                history_len
+            }
+            Key::Backspace => {
+                search.query.pop();
+                history_len
+            }
+            Key::Ctrl('r') => search.match_position.unwrap_or(history_len),
+            Key::Escape | Key::Ctrl('g') | Key::Ctrl('c') => {
+                self.line_editor = search.line_editor_original.clone();
+                self.history_search = None;
+                return None;
+            }
+            Key::Enter => {
+                self.history_search = None;
+                return None;
+            }
+            key => {
+                self.history_search = None;
+                return Some(key);
+            }
+        };
+
+        if search.query.is_empty() {
+            search.is_failing = false;
+            search.match_position = None;
+            self.line_editor = search.line_editor_original.clone();
+            return None;
+        }
+
+        match self.history.search_backward(&search.query, before) {
+            Some(match_position) => {
+                search.is_failing = false;
+                search.match_position = Some(match_position);
+                self.line_editor = LineEditor::new(self.history.entries()[match_position].clone());
+            }
+            None => search.is_failing = true,
+        }
+
+        None
+    }
+}
+
+///
+/// Map keys onto prompt actions, using
+/// Readline's default Emacs key bindings
+///
+fn key_action(key: Key) -> PromptAction {
+    match key {
+        Key::Enter => PromptAction::Accept,
+        Key::Escape | Key::Ctrl('c') => PromptAction::Cancel,
+        Key::Home | Key::Ctrl('a') => PromptAction::Edit(LineEditor::beginning_of_line),
+        Key::End | Key::Ctrl('e') => PromptAction::Edit(LineEditor::end_of_line),
+        Key::Left | Key::Ctrl('b') => PromptAction::Edit(LineEditor::backward_char),
+        Key::Right | Key::Ctrl('f') => PromptAction::Edit(LineEditor::forward_char),
+        Key::CtrlLeft | Key::Alt('b') => PromptAction::Edit(LineEditor::backward_word),
+        Key::CtrlRight | Key::Alt('f') => PromptAction::Edit(LineEditor::forward_word),
+        Key::Backspace => PromptAction::Edit(LineEditor::backward_delete_char),
+        Key::Delete | Key::Ctrl('d') => PromptAction::Edit(LineEditor::delete_char),
+        Key::Ctrl('w') => PromptAction::Edit(LineEditor::unix_word_rubout),
+        Key::AltBackspace => PromptAction::Edit(LineEditor::backward_kill_word),
+        Key::Alt('d') => PromptAction::Edit(LineEditor::kill_word),
+        Key::Ctrl('u') => PromptAction::Edit(LineEditor::unix_line_discard),
+        Key::Ctrl('k') => PromptAction::Edit(LineEditor::kill_line),
+        Key::Up | Key::Ctrl('p') => PromptAction::PreviousHistory,
+        Key::Down | Key::Ctrl('n') => PromptAction::NextHistory,
+        Key::Ctrl('r') => PromptAction::ReverseSearchHistory,
+        Key::Char(c) => PromptAction::Insert(c),
+        Key::Paste(text) => PromptAction::Paste(text),
+        _ => PromptAction::Ignore,
+    }
+}
+
+///
+/// Fit pasted text onto the command line, with
+/// line breaks turned into spaces rather than
+/// accepting the command
+///
+fn paste_to_line(text: &str) -> String {
+    text.trim_end_matches(['\r', '\n'])
+        .replace("\r\n", " ")
+        .chars()
+        .filter_map(|c| match c {
+            '\r' | '\n' => Some(' '),
+            '\t' => Some(c),
+            c if c.is_control() => None,
+            c => Some(c),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Prompt, PromptResult};
+    use crate::history::History;
+    use crate::keys::Key;
+
+    fn prompt(command_text: &str) -> Prompt {
+        let history = History::in_memory(&["rg foo", "jq .items", "rg bar"]);
+        Prompt::new(String::from(command_text), history)
+    }
+
+    fn type_keys(prompt: &mut Prompt, keys: &[Key]) {
+        for key in keys {
+            let prompt_result = prompt.handle_key(key.clone());
+            assert!(matches!(prompt_result, PromptResult::Continue));
+        }
+    }
+
+    #[test]
+    fn steps_through_history() {
+        let mut prompt = prompt("draft");
+
+        type_keys(&mut prompt, &[Key::Up, Key::Up]);
+        assert_eq!(prompt.line_editor().text(), "jq .items");
+
+        type_keys(&mut prompt, &[Key::Up, Key::Up]);
+        assert_eq!(prompt.line_editor().text(), "rg foo");
+
+        type_keys(&mut prompt, &[Key::Down, Key::Down, Key::Down, Key::Down]);
+        assert_eq!(prompt.line_editor().text(), "draft");
+    }
+
+    #[test]
+    fn searches_history_incrementally() {
+        let mut prompt = prompt("draft");
+
+        type_keys(
+            &mut prompt,
+            &[Key::Ctrl('r'), Key::Char('r'), Key::Char('g')],
+        );
+        assert_eq!(prompt.line_editor().text(), "rg bar");
+
+        type_keys(&mut prompt, &[Key::Ctrl('r')]);
+        assert_eq!(prompt.line_editor().text(), "rg foo");
+
+        type_keys(&mut prompt, &[Key::Ctrl('r')]);
+        assert_eq!(prompt.line_editor().text(), "rg foo");
+        assert!(prompt.history_search().unwrap().is_failing);
+
+        type_keys(&mut prompt, &[Key::End, Key::Char('!')]);
+        assert!(prompt.history_search().is_none());
+        assert_eq!(prompt.line_editor().text(), "rg foo!");
+    }
+
+    #[test]
+    fn restores_line_when_search_is_cancelled() {
+        let mut prompt = prompt("draft");
+
+        type_keys(&mut prompt, &[Key::Ctrl('r'), Key::Char('j'), Key::Escape]);
+        assert!(prompt.history_search().is_none());
+        assert_eq!(prompt.line_editor().text(), "draft");
+    }
+
+    #[test]
+    fn pastes_multiple_lines_onto_one() {
+        let mut prompt = prompt("echo");
+
+        type_keys(&mut prompt, &[Key::Paste(String::from(" a\r\nb\n"))]);
+        assert_eq!(prompt.line_editor().text(), "echo a b");
+    }
+}
@@ -0,0 +1,102 @@
+use nix::fcntl::OFlag;
+use nix::libc;
+use nix::pty::{grantpt, posix_openpt, ptsname, unlockpt};
+use nix::unistd::setsid;
+use std::fs::File;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, IntoRawFd};
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+use crate::result::Result;
+
+///
+/// Open a new pseudoterminal, returning its
+/// master and slave ends
+///
+pub fn open_pty() -> Result<(File, File)> {
+    let pty_master = posix_openpt(OFlag::O_RDWR | OFlag::O_NOCTTY)?;
+
+    grantpt(&pty_master)?;
+    unlockpt(&pty_master)?;
+
+    //
+    // `ptsname` isn't thread safe, but ptys are
+    // only opened from the user input thread
+    //
+    let pty_slave_path = unsafe { ptsname(&pty_master)? };
+    let pty_slave = File::options()
+        .read(true)
+        .write(true)
+        .open(pty_slave_path)?;
+
+    let pty_master = unsafe { File::from_raw_fd(pty_master.into_raw_fd()) };
+
+    Ok((pty_master, pty_slave))
+}
+
+///
+/// Start the command in a session of its own,
+/// with the pty slave on its stdout as the
+/// controlling terminal
+///
+/// The command then leads a process group
+/// holding everything it starts, such as the
+/// rest of a pipeline, which can all be killed
+/// together.
+///
+pub fn start_session(command: &mut Command) {
+    //
+    // Runs in the forked child, so sticks to
+    // async-signal-safe calls
+    //
+    unsafe {
+        command.pre_exec(|| {
+            setsid()?;
+            if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCSCTTY, 0) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+///
+/// Set the window size of the pseudoterminal,
+/// from either end. Programs running on it get
+/// a SIGWINCH if the size changed.
+///
+pub fn set_pty_size(pty: &File, rows: usize, cols: usize) -> Result<()> {
+    let winsize = libc::winsize {
+        ws_row: rows.try_into().unwrap_or(u16::MAX),
+        ws_col: cols.try_into().unwrap_or(u16::MAX),
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    if unsafe { libc::ioctl(pty.as_raw_fd(), libc::TIOCSWINSZ, &winsize) } < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    Ok(())
+}
+
+///
+/// Rows and columns of the terminal, taking it
+/// to be 24x80 if it doesn't know its size
+///
+pub fn terminal_size(terminal: &File) -> Result<(usize, usize)> {
+    let mut winsize = libc::winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    if unsafe { libc::ioctl(terminal.as_raw_fd(), libc::TIOCGWINSZ, &mut winsize) } < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    match (winsize.ws_row, winsize.ws_col) {
+        (0, _) | (_, 0) => Ok((24, 80)),
+        (rows, cols) => Ok((rows.into(), cols.into())),
+    }
+}
@@ -0,0 +1,221 @@
+use std::io::{self, Write};
+
+use crate::screen::{Cell, Color, Style};
+
+///
+/// Draws screens onto part of the terminal,
+/// writing only the cells which changed since
+/// the last frame
+///
+pub struct Renderer {
+    /// Row of the terminal the screen starts on,
+    /// counting from 1
+    first_row: usize,
+
+    /// Cells as last drawn, where any missing
+    /// are blank
+    drawn_lines: Vec<Vec<Cell>>,
+}
+
+impl Renderer {
+    pub fn new(first_row: usize) -> Self {
+        Self {
+            first_row,
+            drawn_lines: Vec::new(),
+        }
+    }
+
+    ///
+    /// Forget what was drawn, once the display
+    /// has been erased
+    ///
+    pub fn clear(&mut self) {
+        self.drawn_lines.clear();
+    }
+
+    pub fn render(
+        &mut self,
+        output: &mut impl Write,
+        lines: &[Vec<Cell>],
+        is_dimmed: bool,
+    ) -> io::Result<()> {
+        let lines: Vec<Vec<Cell>> = lines
+            .iter()
+            .map(|line| {
+                line.iter()
+                    .map(|cell| Cell {
+                        style: Style {
+                            is_dim: cell.style.is_dim || is_dimmed,
+                            ..cell.style
+                        },
+                        ..*cell
+                    })
+                    .collect()
+            })
+            .collect();
+
+        //
+        // Where the terminal's cursor and style are
+        // at, if known, to skip moving and styling
+        // between cells drawn one after the other
+        //
+        let mut cursor: Option<(usize, usize)> = None;
+        let mut style_current: Option<Style> = None;
+
+        for (row, line) in lines.iter().enumerate() {
+            for (col, cell) in line.iter().enumerate() {
+                let drawn_cell = self
+                    .drawn_lines
+                    .get(row)
+                    .and_then(|drawn_line| drawn_line.get(col))
+                    .copied()
+                    .unwrap_or_default();
+                //
+                // Second halves of wide characters are
+                // drawn along with the first
+                //
+                if *cell == drawn_cell || cell.is_wide_continuation() {
+                    continue;
+                }
+
+                if cursor != Some((row, col)) {
+                    write!(output, "\u{1b}[{};{}H", self.first_row + row, col + 1)?;
+                }
+                if style_current != Some(cell.style) {
+                    write_style(output, &cell.style)?;
+                    style_current = Some(cell.style);
+                }
+                write!(output, "{}", cell.character)?;
+
+                //
+                // Once the last column is written to,
+                // the cursor stays put until the next
+                // character wraps it
+                //
+                let width = match line.get(col + 1) {
+                    Some(next_cell) if next_cell.is_wide_continuation() => 2,
+                    _ => 1,
+                };
+                cursor = Some((row, col + width)).filter(|_| col + width < line.len());
+            }
+        }
+
+        if style_current.is_some() {
+            output.write_all("\u{1b}[0m".as_bytes())?;
+        }
+        self.drawn_lines = lines;
+
+        Ok(())
+    }
+}
+
+///
+/// Switch to the style with SGR, starting over
+/// from the default style
+///
+fn write_style(output: &mut impl Write, style: &Style) -> io::Result<()> {
+    output.write_all("\u{1b}[0".as_bytes())?;
+    if style.is_bold {
+        output.write_all(";1".as_bytes())?;
+    }
+    if style.is_dim {
+        output.write_all(";2".as_bytes())?;
+    }
+    if style.is_italic {
+        output.write_all(";3".as_bytes())?;
+    }
+    if style.is_underlined {
+        output.write_all(";4".as_bytes())?;
+    }
+    if style.is_inverse {
+        output.write_all(";7".as_bytes())?;
+    }
+    write_color(output, style.foreground, 30, 90, 38)?;
+    write_color(output, style.background, 40, 100, 48)?;
+    output.write_all("m".as_bytes())?;
+
+    Ok(())
+}
+
+///
+/// Color parameters for SGR, using the shortest
+/// form for the 16 ANSI colors
+///
+fn write_color(
+    output: &mut impl Write,
+    color: Color,
+    ansi_base: u8,
+    bright_base: u8,
+    extended: u8,
+) -> io::Result<()> {
+    match color {
+        Color::Default => {}
+        Color::Indexed(index @ 0..=7) => write!(output, ";{}", ansi_base + index)?,
+        Color::Indexed(index @ 8..=15) => write!(output, ";{}", bright_base + index - 8)?,
+        Color::Indexed(index) => write!(output, ";{};5;{}", extended, index)?,
+        Color::Rgb(r, g, b) => write!(output, ";{};2;{};{};{}", extended, r, g, b)?,
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Renderer;
+    use crate::screen::VirtualTerminal;
+
+    fn render(renderer: &mut Renderer, output: &str, is_dimmed: bool) -> String {
+        let mut virtual_terminal = VirtualTerminal::new(2, 4);
+        virtual_terminal.process(output.as_bytes());
+
+        let mut frame = Vec::new();
+        renderer
+            .render(&mut frame, virtual_terminal.screen().lines(), is_dimmed)
+            .unwrap();
+        String::from_utf8(frame).unwrap()
+    }
+
+    #[test]
+    fn draws_only_cells_which_changed() {
+        let mut renderer = Renderer::new(3);
+        assert_eq!(render(&mut renderer, "", false), "");
+        assert_eq!(
+            render(&mut renderer, "ab\r\ncd", false),
+            "\x1b[3;1H\x1b[0mab\x1b[4;1Hcd\x1b[0m"
+        );
+        assert_eq!(
+            render(&mut renderer, "ab\r\nc\x1b[31mx", false),
+            "\x1b[4;2H\x1b[0;31mx\x1b[0m"
+        );
+        assert_eq!(render(&mut renderer, "ab\r\nc\x1b[31mx", false), "");
+        assert_eq!(
+            render(&mut renderer, "a", false),
+            "\x1b[3;2H\x1b[0m \x1b[4;1H  \x1b[0m"
+        );
+    }
+
+    #[test]
+    fn moves_past_wide_characters_and_the_last_column() {
+        let mut renderer = Renderer::new(1);
+        assert_eq!(
+            render(&mut renderer, "日abc", false),
+            "\x1b[1;1H\x1b[0m日ab\x1b[2;1Hc\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn redraws_everything_once_dimmed() {
+        let mut renderer = Renderer::new(1);
+        render(&mut renderer, "a", false);
+        assert_eq!(
+            render(&mut renderer, "a", true),
+            "\x1b[1;1H\x1b[0;2ma   \x1b[2;1H    \x1b[0m"
+        );
+
+        renderer.clear();
+        assert_eq!(
+            render(&mut renderer, "a", false),
+            "\x1b[1;1H\x1b[0ma\x1b[0m"
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use std::time::{Duration, Instant};
+
+use crate::command::CommandStatus;
+
+///
+/// How a run of the command is going, for the
+/// status line
+///
+pub struct RunStatus {
+    started_at: Instant,
+    ended_at: Option<Instant>,
+    command_status: Option<CommandStatus>,
+    is_timed_out: bool,
+    is_truncated: bool,
+    output_bytes: usize,
+    output_lines: usize,
+}
+
+impl RunStatus {
+    pub fn new(started_at: Instant) -> Self {
+        Self {
+            started_at,
+            ended_at: None,
+            command_status: None,
+            is_timed_out: false,
+            is_truncated: false,
+            output_bytes: 0,
+            output_lines: 0,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.ended_at.is_none()
+    }
+
+    pub fn output_lines(&self) -> usize {
+        self.output_lines
+    }
+
+    pub fn add_output(&mut self, output: &[u8]) {
+        self.output_bytes += output.len();
+        self.output_lines += output.iter().filter(|&&byte| byte == b'\n').count();
+    }
+
+    pub fn set_command_status(&mut self, command_status: CommandStatus, now: Instant) {
+        if command_status.is_exit() {
+            self.ended_at = Some(now);
+        }
+        self.command_status = match command_status {
+            CommandStatus::Continued => None,
+            command_status => Some(command_status),
+        };
+    }
+
+    pub fn set_timed_out(&mut self) {
+        self.is_timed_out = true;
+    }
+
+    pub fn set_truncated(&mut self) {
+        self.is_truncated = true;
+    }
+
+    ///
+    /// One line summary, such as `exited with 1 ·
+    /// 0.3s · 12 lines · 1.5 KiB`
+    ///
+    pub fn describe(&self, now: Instant) -> String {
+        let state = match (self.is_timed_out, self.is_truncated, &self.command_status) {
+            (true, _, _) => String::from("timed out"),
+            (false, true, _) => String::from("truncated"),
+            (false, false, Some(command_status)) => command_status.to_string(),
+            (false, false, None) => String::from("running"),
+        };
+        let duration = self.ended_at.unwrap_or(now) - self.started_at;
+
+        format!(
+            "{} · {} · {} · {}",
+            state,
+            format_duration(duration),
+            format_lines(self.output_lines),
+            format_bytes(self.output_bytes)
+        )
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    format!("{:.1}s", duration.as_secs_f64())
+}
+
+pub fn format_lines(lines: usize) -> String {
+    match lines {
+        1 => String::from("1 line"),
+        lines => format!("{} lines", lines),
+    }
+}
+
+fn format_bytes(bytes: usize) -> String {
+    const UNITS: [&str; 3] = ["KiB", "MiB", "GiB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = UNITS[0];
+    for next_unit in &UNITS[1..] {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next_unit;
+    }
+
+    format!("{:.1} {}", size, unit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_bytes, RunStatus};
+    use crate::command::CommandStatus;
+    use nix::sys::signal::Signal;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn describes_runs() {
+        let started_at = Instant::now();
+        let mut run_status = RunStatus::new(started_at);
+        run_status.add_output(b"a\nb\n");
+        assert_eq!(
+            run_status.describe(started_at + Duration::from_millis(250)),
+            "running · 0.2s · 2 lines · 4 B"
+        );
+
+        run_status.set_command_status(
+            CommandStatus::Exited(1),
+            started_at + Duration::from_millis(1500),
+        );
+        assert_eq!(
+            run_status.describe(started_at + Duration::from_secs(9)),
+            "exited with 1 · 1.5s · 2 lines · 4 B"
+        );
+        assert!(!run_status.is_running());
+    }
+
+    #[test]
+    fn describes_timeouts_over_how_they_ended() {
+        let started_at = Instant::now();
+        let mut run_status = RunStatus::new(started_at);
+        run_status.set_timed_out();
+        run_status.set_command_status(
+            CommandStatus::Signaled {
+                signal: Signal::SIGTERM,
+                core_dumped: false,
+            },
+            started_at,
+        );
+        assert_eq!(
+            run_status.describe(started_at),
+            "timed out · 0.0s · 0 lines · 0 B"
+        );
+    }
+
+    #[test]
+    fn describes_truncated_runs() {
+        let started_at = Instant::now();
+        let mut run_status = RunStatus::new(started_at);
+        run_status.add_output(b"a\n");
+        run_status.set_truncated();
+        assert_eq!(
+            run_status.describe(started_at),
+            "truncated · 0.0s · 1 line · 2 B"
+        );
+    }
+
+    #[test]
+    fn formats_bytes() {
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024), "3.0 MiB");
+    }
+}
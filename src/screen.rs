@@ -0,0 +1,734 @@
+use std::mem;
+use unicode_width::UnicodeWidthChar;
+use vte::{Params, ParamsIter, Parser, Perform};
+
+///
+/// Width of tab stops, which can't be changed
+///
+const TAB_WIDTH: usize = 8;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Color {
+    #[default]
+    Default,
+    /// One of the 256 colors of the palette,
+    /// where the first 16 are the ANSI colors
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Style {
+    pub foreground: Color,
+    pub background: Color,
+    pub is_bold: bool,
+    pub is_dim: bool,
+    pub is_italic: bool,
+    pub is_underlined: bool,
+    pub is_inverse: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cell {
+    /// Left as `'\0'` in the column covered by the
+    /// second half of a wide character
+    pub character: char,
+    pub style: Style,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self {
+            character: ' ',
+            style: Style::default(),
+        }
+    }
+}
+
+impl Cell {
+    fn blank(style: Style) -> Self {
+        //
+        // Erased cells keep the background color,
+        // as in xterm, but nothing else
+        //
+        Self {
+            character: ' ',
+            style: Style {
+                background: style.background,
+                ..Style::default()
+            },
+        }
+    }
+
+    pub fn is_wide_continuation(&self) -> bool {
+        self.character == '\0'
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Cursor {
+    row: usize,
+    col: usize,
+    style: Style,
+
+    /// Whether the last column was just written to,
+    /// so the next character goes on the next line
+    is_wrap_pending: bool,
+}
+
+///
+/// Grid of cells a command has drawn on, the way
+/// a VT100 or xterm would show it
+///
+/// Only the visible screen is kept, as anything
+/// scrolled off the top is out of the preview
+/// anyway.
+///
+#[derive(Clone)]
+pub struct Screen {
+    rows: usize,
+    cols: usize,
+    lines: Vec<Vec<Cell>>,
+
+    /// Lines of the main screen, kept while the
+    /// alternate screen is shown
+    main_lines: Option<Vec<Vec<Cell>>>,
+
+    cursor: Cursor,
+    saved_cursor: Cursor,
+
+    /// First and last rows of the scrolling
+    /// region, inclusive
+    scroll_top: usize,
+    scroll_bottom: usize,
+
+    is_autowrap: bool,
+}
+
+impl Screen {
+    pub fn new(rows: usize, cols: usize) -> Self {
+        let rows = rows.max(1);
+        let cols = cols.max(1);
+
+        Self {
+            rows,
+            cols,
+            lines: vec![vec![Cell::default(); cols]; rows],
+            main_lines: None,
+            cursor: Cursor::default(),
+            saved_cursor: Cursor::default(),
+            scroll_top: 0,
+            scroll_bottom: rows - 1,
+            is_autowrap: true,
+        }
+    }
+
+    pub fn lines(&self) -> &[Vec<Cell>] {
+        &self.lines
+    }
+
+    pub fn cursor_col(&self) -> usize {
+        self.cursor.col
+    }
+
+    ///
+    /// Text on screen, without trailing blanks
+    ///
+    #[cfg(test)]
+    pub fn contents(&self) -> String {
+        let lines: Vec<String> = self
+            .lines
+            .iter()
+            .map(|line| {
+                let line: String = line
+                    .iter()
+                    .filter(|cell| !cell.is_wide_continuation())
+                    .map(|cell| cell.character)
+                    .collect();
+                line.trim_end().to_string()
+            })
+            .collect();
+        lines.join("\n").trim_end().to_string()
+    }
+
+    ///
+    /// Change the size without rewrapping lines,
+    /// as xterm does, keeping the cursor's line
+    /// on screen by dropping lines off the top
+    ///
+    pub fn resize(&mut self, rows: usize, cols: usize) {
+        let rows = rows.max(1);
+        let cols = cols.max(1);
+
+        if rows <= self.cursor.row {
+            let dropped_rows = self.cursor.row + 1 - rows;
+            self.lines.drain(..dropped_rows);
+            self.cursor.row -= dropped_rows;
+        }
+        resize_lines(&mut self.lines, rows, cols);
+        if let Some(main_lines) = &mut self.main_lines {
+            resize_lines(main_lines, rows, cols);
+        }
+
+        self.rows = rows;
+        self.cols = cols;
+        self.scroll_top = 0;
+        self.scroll_bottom = rows - 1;
+        self.move_to(self.cursor.row, self.cursor.col);
+        self.saved_cursor.row = self.saved_cursor.row.min(rows - 1);
+        self.saved_cursor.col = self.saved_cursor.col.min(cols - 1);
+    }
+
+    fn blank_line(&self) -> Vec<Cell> {
+        vec![Cell::blank(self.cursor.style); self.cols]
+    }
+
+    fn move_to(&mut self, row: usize, col: usize) {
+        self.cursor.row = row.min(self.rows - 1);
+        self.cursor.col = col.min(self.cols - 1);
+        self.cursor.is_wrap_pending = false;
+    }
+
+    ///
+    /// Rows the cursor can move between, which stop
+    /// at the scrolling region if it's inside it
+    ///
+    fn cursor_bounds(&self) -> (usize, usize) {
+        let top = if self.cursor.row >= self.scroll_top {
+            self.scroll_top
+        } else {
+            0
+        };
+        let bottom = if self.cursor.row <= self.scroll_bottom {
+            self.scroll_bottom
+        } else {
+            self.rows - 1
+        };
+        (top, bottom)
+    }
+
+    fn scroll_up(&mut self, top: usize, count: usize) {
+        let count = count.min(self.scroll_bottom + 1 - top);
+        self.lines.drain(top..top + count);
+        let blank_line = self.blank_line();
+        for _ in 0..count {
+            self.lines
+                .insert(self.scroll_bottom + 1 - count, blank_line.clone());
+        }
+    }
+
+    fn scroll_down(&mut self, top: usize, count: usize) {
+        let count = count.min(self.scroll_bottom + 1 - top);
+        self.lines
+            .drain(self.scroll_bottom + 1 - count..=self.scroll_bottom);
+        let blank_line = self.blank_line();
+        for _ in 0..count {
+            self.lines.insert(top, blank_line.clone());
+        }
+    }
+
+    fn line_feed(&mut self) {
+        if self.cursor.row == self.scroll_bottom {
+            self.scroll_up(self.scroll_top, 1);
+        } else if self.cursor.row < self.rows - 1 {
+            self.cursor.row += 1;
+        }
+        self.cursor.is_wrap_pending = false;
+    }
+
+    fn reverse_line_feed(&mut self) {
+        if self.cursor.row == self.scroll_top {
+            self.scroll_down(self.scroll_top, 1);
+        } else if self.cursor.row > 0 {
+            self.cursor.row -= 1;
+        }
+        self.cursor.is_wrap_pending = false;
+    }
+
+    fn erase(&mut self, row: usize, cols: impl Iterator<Item = usize>) {
+        let blank = Cell::blank(self.cursor.style);
+        for col in cols {
+            self.lines[row][col] = blank;
+        }
+    }
+
+    ///
+    /// Blank out what's left of a wide character
+    /// about to be partly overwritten
+    ///
+    fn split_wide_character(&mut self, row: usize, col: usize) {
+        let blank = Cell::blank(self.cursor.style);
+        let line = &mut self.lines[row];
+        if line[col].is_wide_continuation() && col > 0 {
+            line[col - 1] = blank;
+        }
+        if line.get(col + 1).is_some_and(Cell::is_wide_continuation) {
+            line[col + 1] = blank;
+        }
+    }
+
+    fn set_alternate_screen(&mut self, is_alternate: bool) {
+        if is_alternate == self.main_lines.is_some() {
+            return;
+        }
+
+        if is_alternate {
+            self.saved_cursor = self.cursor;
+            let lines = vec![self.blank_line(); self.rows];
+            self.main_lines = Some(mem::replace(&mut self.lines, lines));
+        } else if let Some(main_lines) = self.main_lines.take() {
+            self.lines = main_lines;
+            self.cursor = self.saved_cursor;
+        }
+    }
+
+    fn set_private_mode(&mut self, mode: u16, is_set: bool) {
+        match mode {
+            7 => self.is_autowrap = is_set,
+            47 | 1047 | 1049 => self.set_alternate_screen(is_set),
+            _ => {}
+        }
+    }
+
+    fn set_scrolling_region(&mut self, top: usize, bottom: usize) {
+        let bottom = bottom.min(self.rows - 1);
+        if top < bottom {
+            self.scroll_top = top;
+            self.scroll_bottom = bottom;
+            self.move_to(0, 0);
+        }
+    }
+
+    fn erase_in_display(&mut self, mode: u16) {
+        let Cursor { row, col, .. } = self.cursor;
+        match mode {
+            0 => {
+                self.erase(row, col..self.cols);
+                for row in row + 1..self.rows {
+                    self.erase(row, 0..self.cols);
+                }
+            }
+            1 => {
+                for row in 0..row {
+                    self.erase(row, 0..self.cols);
+                }
+                self.erase(row, 0..col + 1);
+            }
+            2 => {
+                for row in 0..self.rows {
+                    self.erase(row, 0..self.cols);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn erase_in_line(&mut self, mode: u16) {
+        let Cursor { row, col, .. } = self.cursor;
+        match mode {
+            0 => self.erase(row, col..self.cols),
+            1 => self.erase(row, 0..col + 1),
+            2 => self.erase(row, 0..self.cols),
+            _ => {}
+        }
+    }
+
+    fn insert_lines(&mut self, count: usize) {
+        let row = self.cursor.row;
+        if row < self.scroll_top || row > self.scroll_bottom {
+            return;
+        }
+        self.scroll_down(row, count);
+        self.move_to(row, 0);
+    }
+
+    fn delete_lines(&mut self, count: usize) {
+        let row = self.cursor.row;
+        if row < self.scroll_top || row > self.scroll_bottom {
+            return;
+        }
+        self.scroll_up(row, count);
+        self.move_to(row, 0);
+    }
+
+    fn insert_blanks(&mut self, count: usize) {
+        let Cursor { row, col, .. } = self.cursor;
+        let count = count.min(self.cols - col);
+        let blank = Cell::blank(self.cursor.style);
+        let line = &mut self.lines[row];
+        line.truncate(self.cols - count);
+        line.splice(col..col, vec![blank; count]);
+        self.cursor.is_wrap_pending = false;
+    }
+
+    fn delete_characters(&mut self, count: usize) {
+        let Cursor { row, col, .. } = self.cursor;
+        let count = count.min(self.cols - col);
+        let blank = Cell::blank(self.cursor.style);
+        let line = &mut self.lines[row];
+        line.drain(col..col + count);
+        line.resize(self.cols, blank);
+        self.cursor.is_wrap_pending = false;
+    }
+
+    fn select_graphic_rendition(&mut self, params: &Params) {
+        let style = &mut self.cursor.style;
+        let mut params = params.iter();
+        while let Some(param) = params.next() {
+            match param {
+                [0] => *style = Style::default(),
+                [1] => style.is_bold = true,
+                [2] => style.is_dim = true,
+                [3] => style.is_italic = true,
+                [4, 0] | [24] => style.is_underlined = false,
+                [4, ..] => style.is_underlined = true,
+                [7] => style.is_inverse = true,
+                [22] => {
+                    style.is_bold = false;
+                    style.is_dim = false;
+                }
+                [23] => style.is_italic = false,
+                [27] => style.is_inverse = false,
+                [n @ 30..=37] => style.foreground = Color::Indexed((n - 30) as u8),
+                [38, ..] => {
+                    if let Some(color) = parse_extended_color(param, &mut params) {
+                        style.foreground = color;
+                    }
+                }
+                [39] => style.foreground = Color::Default,
+                [n @ 40..=47] => style.background = Color::Indexed((n - 40) as u8),
+                [48, ..] => {
+                    if let Some(color) = parse_extended_color(param, &mut params) {
+                        style.background = color;
+                    }
+                }
+                [49] => style.background = Color::Default,
+                [n @ 90..=97] => style.foreground = Color::Indexed((n - 90 + 8) as u8),
+                [n @ 100..=107] => style.background = Color::Indexed((n - 100 + 8) as u8),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Perform for Screen {
+    fn print(&mut self, character: char) {
+        //
+        // Combining characters and the like have
+        // no cell of their own, and are dropped
+        //
+        let width = match character.width() {
+            Some(width) if 0 < width && width <= self.cols => width,
+            _ => return,
+        };
+
+        if self.cursor.is_wrap_pending && self.is_autowrap {
+            self.line_feed();
+            self.cursor.col = 0;
+        }
+        if self.cols < self.cursor.col + width {
+            if self.is_autowrap {
+                self.line_feed();
+                self.cursor.col = 0;
+            } else {
+                self.cursor.col = self.cols - width;
+            }
+        }
+
+        let Cursor {
+            row, col, style, ..
+        } = self.cursor;
+        self.split_wide_character(row, col);
+        self.lines[row][col] = Cell { character, style };
+        if width == 2 {
+            self.split_wide_character(row, col + 1);
+            self.lines[row][col + 1] = Cell {
+                character: '\0',
+                style,
+            };
+        }
+
+        if col + width == self.cols {
+            self.cursor.col = self.cols - 1;
+            self.cursor.is_wrap_pending = true;
+        } else {
+            self.cursor.col = col + width;
+            self.cursor.is_wrap_pending = false;
+        }
+    }
+
+    fn execute(&mut self, byte: u8) {
+        match byte {
+            // Backspace
+            0x08 => {
+                self.cursor.col = self.cursor.col.saturating_sub(1);
+                self.cursor.is_wrap_pending = false;
+            }
+            // Tab
+            b'\t' => {
+                let col = (self.cursor.col / TAB_WIDTH + 1) * TAB_WIDTH;
+                self.move_to(self.cursor.row, col);
+            }
+            //
+            // Line feed, vertical tab and form feed
+            // all just move down, as carriage
+            // returns are added by the pty
+            //
+            b'\n' | 0x0b | 0x0c => self.line_feed(),
+            b'\r' => self.move_to(self.cursor.row, 0),
+            _ => {}
+        }
+    }
+
+    fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], ignore: bool, action: char) {
+        if ignore {
+            return;
+        }
+
+        let values: Vec<u16> = params.iter().map(|param| param[0]).collect();
+        let value = |index: usize| values.get(index).copied().unwrap_or(0);
+        //
+        // Counts and positions of 0 mean 1
+        //
+        let count = |index: usize| value(index).max(1) as usize;
+        let Cursor { row, col, .. } = self.cursor;
+
+        match (intermediates, action) {
+            ([], 'A') => {
+                let (top, _) = self.cursor_bounds();
+                self.move_to(row.saturating_sub(count(0)).max(top), col);
+            }
+            ([], 'B' | 'e') => {
+                let (_, bottom) = self.cursor_bounds();
+                self.move_to((row + count(0)).min(bottom), col);
+            }
+            ([], 'C' | 'a') => self.move_to(row, col + count(0)),
+            ([], 'D') => self.move_to(row, col.saturating_sub(count(0))),
+            ([], 'E') => {
+                let (_, bottom) = self.cursor_bounds();
+                self.move_to((row + count(0)).min(bottom), 0);
+            }
+            ([], 'F') => {
+                let (top, _) = self.cursor_bounds();
+                self.move_to(row.saturating_sub(count(0)).max(top), 0);
+            }
+            ([], 'G' | '`') => self.move_to(row, count(0) - 1),
+            ([], 'd') => self.move_to(count(0) - 1, col),
+            ([], 'H' | 'f') => self.move_to(count(0) - 1, count(1) - 1),
+            ([], 'J') => self.erase_in_display(value(0)),
+            ([], 'K') => self.erase_in_line(value(0)),
+            ([], 'L') => self.insert_lines(count(0)),
+            ([], 'M') => self.delete_lines(count(0)),
+            ([], '@') => self.insert_blanks(count(0)),
+            ([], 'P') => self.delete_characters(count(0)),
+            ([], 'X') => {
+                let end = (col + count(0)).min(self.cols);
+                self.erase(row, col..end);
+                self.cursor.is_wrap_pending = false;
+            }
+            ([], 'S') => self.scroll_up(self.scroll_top, count(0)),
+            ([], 'T') => self.scroll_down(self.scroll_top, count(0)),
+            ([], 'm') => self.select_graphic_rendition(params),
+            ([], 'r') => {
+                let bottom = match value(1) {
+                    0 => self.rows,
+                    bottom => bottom as usize,
+                };
+                self.set_scrolling_region(count(0) - 1, bottom - 1);
+            }
+            ([], 's') => self.saved_cursor = self.cursor,
+            ([], 'u') => self.cursor = self.saved_cursor,
+            ([b'?'], 'h' | 'l') => {
+                for mode in &values {
+                    self.set_private_mode(*mode, action == 'h');
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn esc_dispatch(&mut self, intermediates: &[u8], ignore: bool, byte: u8) {
+        if ignore || !intermediates.is_empty() {
+            return;
+        }
+
+        match byte {
+            b'7' => self.saved_cursor = self.cursor,
+            b'8' => self.cursor = self.saved_cursor,
+            b'D' => self.line_feed(),
+            b'E' => {
+                self.line_feed();
+                self.cursor.col = 0;
+            }
+            b'M' => self.reverse_line_feed(),
+            b'c' => *self = Self::new(self.rows, self.cols),
+            _ => {}
+        }
+    }
+}
+
+fn resize_lines(lines: &mut Vec<Vec<Cell>>, rows: usize, cols: usize) {
+    let blank = Cell::default();
+    for line in lines.iter_mut() {
+        //
+        // Drop wide characters cut in half
+        //
+        if line.get(cols).is_some_and(Cell::is_wide_continuation) {
+            line[cols - 1] = blank;
+        }
+        line.resize(cols, blank);
+    }
+    lines.resize(rows, vec![blank; cols]);
+}
+
+///
+/// Color from `38` or `48` in SGR, given either
+/// with subparameters, like `38:5:n`, or as the
+/// parameters that follow, like `38;5;n`
+///
+fn parse_extended_color(param: &[u16], params: &mut ParamsIter) -> Option<Color> {
+    let mut values: Vec<u16> = param[1..].to_vec();
+    if values.is_empty() {
+        let color_space = params.next()?[0];
+        let color_len = match color_space {
+            5 => 1,
+            2 => 3,
+            _ => return None,
+        };
+        values.push(color_space);
+        for _ in 0..color_len {
+            values.push(params.next()?[0]);
+        }
+    }
+
+    match values[..] {
+        [5, index] => Some(Color::Indexed(index as u8)),
+        [2, r, g, b] | [2, _, r, g, b] => Some(Color::Rgb(r as u8, g as u8, b as u8)),
+        _ => None,
+    }
+}
+
+///
+/// Screen fed by the parser, which holds on to
+/// escape sequences and characters cut off at
+/// the end of one read until the next
+///
+pub struct VirtualTerminal {
+    parser: Parser,
+    screen: Screen,
+}
+
+impl VirtualTerminal {
+    pub fn new(rows: usize, cols: usize) -> Self {
+        Self {
+            parser: Parser::new(),
+            screen: Screen::new(rows, cols),
+        }
+    }
+
+    pub fn screen(&self) -> &Screen {
+        &self.screen
+    }
+
+    pub fn resize(&mut self, rows: usize, cols: usize) {
+        self.screen.resize(rows, cols);
+    }
+
+    pub fn process(&mut self, output: &[u8]) {
+        for byte in output {
+            self.parser.advance(&mut self.screen, *byte);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Color, Style, VirtualTerminal};
+
+    fn screen_contents(rows: usize, cols: usize, output: &str) -> String {
+        let mut virtual_terminal = VirtualTerminal::new(rows, cols);
+        virtual_terminal.process(output.as_bytes());
+        virtual_terminal.screen().contents()
+    }
+
+    #[test]
+    fn wraps_and_scrolls() {
+        assert_eq!(screen_contents(2, 4, "abcdef"), "abcd\nef");
+        assert_eq!(screen_contents(2, 4, "a\r\nb\r\nc\r\n"), "c");
+        assert_eq!(screen_contents(2, 4, "abcd\r\n"), "abcd");
+    }
+
+    #[test]
+    fn moves_the_cursor_and_erases() {
+        assert_eq!(screen_contents(3, 8, "abc\x1b[2;3Hx"), "abc\n  x");
+        assert_eq!(screen_contents(2, 8, "abcdef\x1b[3D\x1b[K"), "abc");
+        assert_eq!(screen_contents(2, 8, "ab\r\ncd\x1b[2J\x1b[Hx"), "x");
+        assert_eq!(screen_contents(1, 8, "progress\r50%\x1b[K"), "50%");
+    }
+
+    #[test]
+    fn keeps_partial_sequences_for_the_next_read() {
+        let mut virtual_terminal = VirtualTerminal::new(1, 8);
+        virtual_terminal.process(b"a\x1b[");
+        virtual_terminal.process(b"Db\xc3");
+        virtual_terminal.process(b"\xa9");
+        assert_eq!(virtual_terminal.screen().contents(), "bé");
+    }
+
+    #[test]
+    fn restores_the_main_screen() {
+        assert_eq!(screen_contents(2, 8, "main\x1b[?1049h\x1b[Hfull"), "full");
+        assert_eq!(
+            screen_contents(2, 8, "main\x1b[?1049hfull\x1b[?1049l!"),
+            "main!"
+        );
+    }
+
+    #[test]
+    fn scrolls_within_the_scrolling_region() {
+        assert_eq!(
+            screen_contents(3, 8, "top\x1b[2;3r\x1b[3;1Ha\r\nb\r\nc"),
+            "top\nb\nc"
+        );
+    }
+
+    #[test]
+    fn lays_out_wide_characters() {
+        assert_eq!(screen_contents(2, 3, "a日本"), "a日\n本");
+    }
+
+    #[test]
+    fn resizes_keeping_the_cursor_on_screen() {
+        let mut virtual_terminal = VirtualTerminal::new(3, 4);
+        virtual_terminal.process("a\r\nb\r\nc日".as_bytes());
+
+        virtual_terminal.resize(2, 2);
+        assert_eq!(virtual_terminal.screen().contents(), "b\nc");
+
+        virtual_terminal.resize(3, 4);
+        virtual_terminal.process(b"d\r\ne");
+        assert_eq!(virtual_terminal.screen().contents(), "b\ncd\ne");
+    }
+
+    #[test]
+    fn tracks_styles() {
+        let mut virtual_terminal = VirtualTerminal::new(1, 8);
+        virtual_terminal.process(b"\x1b[1;31ma\x1b[38;5;200;48:2::1:2:3mb\x1b[0mc");
+        let styles: Vec<Style> = virtual_terminal.screen().lines()[0][..3]
+            .iter()
+            .map(|cell| cell.style)
+            .collect();
+
+        let bold_red = Style {
+            foreground: Color::Indexed(1),
+            is_bold: true,
+            ..Style::default()
+        };
+        let colored = Style {
+            foreground: Color::Indexed(200),
+            background: Color::Rgb(1, 2, 3),
+            ..bold_red
+        };
+        assert_eq!(styles, [bold_red, colored, Style::default()]);
+    }
+}
@@ -1,17 +1,29 @@
-use nix::sys::signal::SIGKILL;
-use nix::sys::wait::{waitpid, WaitStatus};
+use nix::sys::signal::{killpg, Signal, SIGCONT, SIGKILL};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::unistd::Pid;
 use std::io;
 use std::sync::mpsc;
 use std::thread;
+use std::time::{Duration, Instant};
 
 use super::command_output_thread::CommandOutputEvent;
 use super::user_input_thread::UserInputEvent;
 use super::user_interface_thread::UserInterfaceEvent;
+use crate::command::CommandStatus;
+use crate::error::Error;
 use crate::result::Result;
 
+///
+/// How often to check on running commands for
+/// having exited
+///
+const REAP_INTERVAL: Duration = Duration::from_millis(20);
+
 pub enum CommandExitEvent {
-    CommandStarted(Pid),
+    CommandStarted(u64, Pid),
+    /// Ask the run to exit, and kill it outright
+    /// if it doesn't within the grace period
+    KillCommand(u64),
     Stop,
 }
 
@@ -20,65 +32,194 @@ pub fn command_exit_thread(
     user_input_events: mpsc::Sender<UserInputEvent>,
     user_interface_events: mpsc::Sender<UserInterfaceEvent>,
     command_exit_events: mpsc::Receiver<CommandExitEvent>,
+    kill_signal: Signal,
+    kill_grace: Duration,
+    timeout: Option<Duration>,
 ) -> thread::JoinHandle<Result<()>> {
     thread::spawn(move || {
         let result = command_exit(
-            &command_output_events,
-            &user_input_events,
             &user_interface_events,
             &command_exit_events,
+            kill_signal,
+            kill_grace,
+            timeout,
         );
 
-        command_output_events.send(CommandOutputEvent::Stop)?;
-        user_input_events.send(UserInputEvent::Stop)?;
-        user_interface_events.send(UserInterfaceEvent::Stop)?;
+        // Errors mean the other thread already stopped
+        let _ = command_output_events.send(CommandOutputEvent::Stop);
+        let _ = user_input_events.send(UserInputEvent::Stop);
+        let _ = user_interface_events.send(UserInterfaceEvent::Stop);
 
         return result;
     })
 }
 
 pub fn command_exit(
-    command_output_events: &mpsc::Sender<CommandOutputEvent>,
-    user_input_events: &mpsc::Sender<UserInputEvent>,
     user_interface_events: &mpsc::Sender<UserInterfaceEvent>,
     command_exit_events: &mpsc::Receiver<CommandExitEvent>,
+    kill_signal: Signal,
+    kill_grace: Duration,
+    timeout: Option<Duration>,
 ) -> Result<()> {
-    for cee in command_exit_events {
-        match cee {
-            CommandExitEvent::Stop => return Ok(()),
-            CommandExitEvent::CommandStarted(pid) => {
-                let wait_status = waitpid(pid, None)?;
-                match wait_status {
-                    WaitStatus::Exited(pid_exited, _)
-                    | WaitStatus::Signaled(pid_exited, SIGKILL, _) => {
-                        let exit_code = if let WaitStatus::Exited(_, exit_code) = wait_status {
-                            Some(exit_code)
-                        } else {
-                            None
-                        };
-
-                        if pid != pid_exited {
-                            return Err(
-                                io::Error::new(io::ErrorKind::Other, "Wrong pid exited").into()
-                            );
-                        }
-
-                        command_output_events.send(CommandOutputEvent::CommandExited)?;
-                        user_input_events.send(UserInputEvent::CommandExited(pid, exit_code))?;
-                        user_interface_events
-                            .send(UserInterfaceEvent::CommandExited(pid, exit_code))?;
-                    }
-                    _ => {
-                        return Err(io::Error::new(
-                            io::ErrorKind::Other,
-                            format!("Wrong child process event: {:?}", wait_status),
-                        )
-                        .into());
+    //
+    // Commands which haven't been reaped yet,
+    // including ones killed in the background
+    // while the next one already started
+    //
+    let mut commands: Vec<CommandRun> = Vec::new();
+    let mut is_stopping = false;
+
+    loop {
+        let event = if is_stopping {
+            if commands.is_empty() {
+                return Ok(());
+            }
+            thread::sleep(REAP_INTERVAL);
+            None
+        } else if commands.is_empty() {
+            Some(command_exit_events.recv()?)
+        } else {
+            match command_exit_events.recv_timeout(REAP_INTERVAL) {
+                Ok(event) => Some(event),
+                Err(mpsc::RecvTimeoutError::Timeout) => None,
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Err(Error::ChannelRecvError),
+            }
+        };
+
+        match event {
+            //
+            // Give whatever is still running the same
+            // chance to clean up before stopping
+            //
+            Some(CommandExitEvent::Stop) => {
+                is_stopping = true;
+                for command in &mut commands {
+                    if command.kill_deadline.is_none() {
+                        command.kill(kill_signal, kill_grace);
                     }
                 }
             }
+            Some(CommandExitEvent::CommandStarted(run_id, pid)) => commands.push(CommandRun {
+                run_id,
+                pid,
+                timeout_deadline: timeout.map(|timeout| Instant::now() + timeout),
+                kill_deadline: None,
+            }),
+            Some(CommandExitEvent::KillCommand(run_id)) => {
+                if let Some(command) = commands.iter_mut().find(|c| c.run_id == run_id) {
+                    command.kill(kill_signal, kill_grace);
+                }
+            }
+            None => {}
+        }
+
+        let mut i = 0;
+        while i < commands.len() {
+            if reap_command(user_interface_events, &commands[i])? {
+                commands.swap_remove(i);
+            } else {
+                i += 1;
+            }
+        }
+
+        //
+        // Out of patience with commands which
+        // didn't exit in time
+        //
+        let now = Instant::now();
+        for command in &mut commands {
+            if command
+                .kill_deadline
+                .is_some_and(|deadline| deadline <= now)
+            {
+                command.kill(SIGKILL, kill_grace);
+            }
+            if command
+                .timeout_deadline
+                .is_some_and(|deadline| deadline <= now)
+            {
+                command.timeout_deadline = None;
+                command.kill(kill_signal, kill_grace);
+
+                // Errors mean the user interface already stopped
+                let _ =
+                    user_interface_events.send(UserInterfaceEvent::CommandTimedOut(command.run_id));
+            }
+        }
+    }
+}
+
+struct CommandRun {
+    run_id: u64,
+
+    /// Pid of the command, which also leads the
+    /// process group of everything it started
+    pid: Pid,
+
+    /// When the command runs out of time, if
+    /// there's a timeout
+    timeout_deadline: Option<Instant>,
+
+    /// When to kill the command outright, once
+    /// asked to exit
+    kill_deadline: Option<Instant>,
+}
+
+impl CommandRun {
+    ///
+    /// Signal the command's whole process group
+    ///
+    /// Only ever done before the command is
+    /// reaped, so its pid can't have been reused.
+    ///
+    fn kill(&mut self, signal: Signal, kill_grace: Duration) {
+        // Errors mean everything already exited
+        let _ = killpg(self.pid, signal);
+
+        //
+        // Wake up stopped commands, so they get
+        // to handle the signal
+        //
+        if signal != SIGKILL {
+            let _ = killpg(self.pid, SIGCONT);
         }
+
+        self.kill_deadline = match signal {
+            SIGKILL => None,
+            _ => Some(Instant::now() + kill_grace),
+        };
+    }
+}
+
+///
+/// Collect the command's status, if it changed,
+/// returning whether it has exited
+///
+fn reap_command(
+    user_interface_events: &mpsc::Sender<UserInterfaceEvent>,
+    command: &CommandRun,
+) -> Result<bool> {
+    let wait_status = waitpid(
+        command.pid,
+        Some(WaitPidFlag::WNOHANG | WaitPidFlag::WUNTRACED | WaitPidFlag::WCONTINUED),
+    )?;
+    if let WaitStatus::StillAlive = wait_status {
+        return Ok(false);
+    }
+    if wait_status.pid() != Some(command.pid) {
+        return Err(io::Error::new(io::ErrorKind::Other, "Wrong pid exited").into());
     }
 
-    Ok(())
+    let command_status = match CommandStatus::from_wait_status(wait_status) {
+        Some(command_status) => command_status,
+        None => return Ok(false),
+    };
+
+    // Errors mean the user interface already stopped
+    let _ = user_interface_events.send(UserInterfaceEvent::CommandStatus(
+        command.run_id,
+        command_status,
+    ));
+
+    Ok(command_status.is_exit())
 }
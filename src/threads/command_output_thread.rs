@@ -1,16 +1,32 @@
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags};
 use std::fs::File;
 use std::io::Read;
+use std::os::fd::AsRawFd;
 use std::sync::mpsc;
 use std::thread;
+use std::time::Duration;
 
 use super::command_exit_thread::CommandExitEvent;
 use super::user_input_thread::UserInputEvent;
 use super::user_interface_thread::UserInterfaceEvent;
+use crate::error::Error;
+use crate::output_limit::OutputLimit;
+use crate::pty::set_pty_size;
 use crate::result::Result;
 
+///
+/// How often to check for new commands and
+/// being stopped, while no output comes in
+///
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
 pub enum CommandOutputEvent {
-    CommandStarted,
-    CommandExited,
+    /// Run with this id started, writing to the
+    /// pseudoterminal with this master
+    CommandStarted(u64, File),
+    /// Preview now has this many rows and columns
+    Resized(usize, usize),
     Stop,
 }
 
@@ -19,91 +35,155 @@ pub fn command_output_thread(
     user_input_events: mpsc::Sender<UserInputEvent>,
     user_interface_events: mpsc::Sender<UserInterfaceEvent>,
     command_output_events: mpsc::Receiver<CommandOutputEvent>,
-    mut pty_master: File,
+    max_output_bytes: Option<usize>,
+    max_output_lines: Option<usize>,
 ) -> thread::JoinHandle<Result<()>> {
     thread::spawn(move || {
         let result = command_output(
+            &command_exit_events,
             &user_interface_events,
             &command_output_events,
-            &mut pty_master,
+            OutputLimit::new(max_output_bytes, max_output_lines),
         );
 
-        command_exit_events.send(CommandExitEvent::Stop)?;
-        user_input_events.send(UserInputEvent::Stop)?;
-        user_interface_events.send(UserInterfaceEvent::Stop)?;
+        // Errors mean the other thread already stopped
+        let _ = command_exit_events.send(CommandExitEvent::Stop);
+        let _ = user_input_events.send(UserInputEvent::Stop);
+        let _ = user_interface_events.send(UserInterfaceEvent::Stop);
 
         return result;
     })
 }
 
 pub fn command_output(
+    command_exit_events: &mpsc::Sender<CommandExitEvent>,
     user_interface_events: &mpsc::Sender<UserInterfaceEvent>,
     command_output_events: &mpsc::Receiver<CommandOutputEvent>,
-    pty_master: &mut File,
+    mut output_limit: OutputLimit,
 ) -> Result<()> {
     let mut buf: [u8; 1000] = [0; 1000];
+    let mut run_id: u64 = 0;
+
+    //
+    // Pseudoterminal of the latest run, until all
+    // its output has been read
+    //
+    let mut pty_master: Option<File> = None;
+
+    //
+    // Size of the preview, once it's been resized,
+    // in case a command started at the old size
+    //
+    let mut pty_size: Option<(usize, usize)> = None;
 
     loop {
-        match command_output_events.recv()? {
-            CommandOutputEvent::Stop => return Ok(()),
-            CommandOutputEvent::CommandExited => {}
-            CommandOutputEvent::CommandStarted => {
-                let read_result = read_command_output(
-                    &user_interface_events,
-                    &command_output_events,
-                    pty_master,
-                    &mut buf,
-                )?;
-
-                if let ReadCommandResult::Stop = read_result {
-                    return Ok(());
+        let is_output_ready = match &pty_master {
+            Some(pty_master) => wait_for_output(pty_master, POLL_INTERVAL)?,
+            None => match command_output_events.recv()? {
+                CommandOutputEvent::CommandStarted(run_id_new, pty_master_new) => {
+                    if let Some((rows, cols)) = pty_size {
+                        set_pty_size(&pty_master_new, rows, cols)?;
+                    }
+                    run_id = run_id_new;
+                    pty_master = Some(pty_master_new);
+                    output_limit.reset();
+                    continue;
+                }
+                CommandOutputEvent::Resized(rows, cols) => {
+                    pty_size = Some((rows, cols));
+                    continue;
+                }
+                CommandOutputEvent::Stop => return Ok(()),
+            },
+        };
+
+        //
+        // Switch over to commands started in the
+        // meantime, leaving behind anything the
+        // previous one didn't get to write
+        //
+        let run_id_previous = run_id;
+        loop {
+            match command_output_events.try_recv() {
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => return Err(Error::ChannelRecvError),
+                Ok(CommandOutputEvent::CommandStarted(run_id_new, pty_master_new)) => {
+                    if let Some((rows, cols)) = pty_size {
+                        set_pty_size(&pty_master_new, rows, cols)?;
+                    }
+                    run_id = run_id_new;
+                    pty_master = Some(pty_master_new);
+                    output_limit.reset();
+                }
+                Ok(CommandOutputEvent::Resized(rows, cols)) => {
+                    pty_size = Some((rows, cols));
+                    if let Some(pty_master) = &pty_master {
+                        set_pty_size(pty_master, rows, cols)?;
+                    }
                 }
+                Ok(CommandOutputEvent::Stop) => return Ok(()),
             }
         }
-    }
-}
-
-enum ReadCommandResult {
-    Continue,
-    Stop,
-}
 
-fn read_command_output(
-    user_interface_events: &mpsc::Sender<UserInterfaceEvent>,
-    command_output_events: &mpsc::Receiver<CommandOutputEvent>,
-    pty_master: &mut File,
-    output_buffer: &mut [u8],
-) -> Result<ReadCommandResult> {
-    let mut bytes_read = 1;
-
-    while 0 < bytes_read {
-        match command_output_events.try_recv() {
-            Err(mpsc::TryRecvError::Empty) => {}
-            Err(mpsc::TryRecvError::Disconnected) => panic!("Unexpected disconnected mpsc channel"),
-            Ok(CommandOutputEvent::CommandStarted) => {
-                panic!("Unexpected premature command start")
-            }
-            Ok(CommandOutputEvent::CommandExited) => return Ok(ReadCommandResult::Continue),
-            Ok(CommandOutputEvent::Stop) => return Ok(ReadCommandResult::Stop),
+        if !is_output_ready || run_id != run_id_previous {
+            continue;
         }
+        let pty_master_current = match &mut pty_master {
+            Some(pty_master_current) => pty_master_current,
+            None => continue,
+        };
+
+        let bytes_read = match pty_master_current.read(&mut buf) {
+            Ok(bytes_read) => bytes_read,
+            //
+            // Linux reports a pty slave with no open
+            // file descriptors left as EIO, rather
+            // than end of file
+            //
+            Err(err) if err.raw_os_error() == Some(Errno::EIO as i32) => 0,
+            Err(err) => return Err(err.into()),
+        };
 
-        bytes_read = pty_master.read(output_buffer)?;
+        //
+        // Everything holding the pty slave open has
+        // exited, so the run has no more output
+        //
         if bytes_read < 1 {
-            return Ok(ReadCommandResult::Stop);
+            pty_master = None;
+            continue;
+        }
+
+        //
+        // Escape sequences and characters cut off
+        // at the end of the read are pieced back
+        // together by the preview's parser
+        //
+        let output_len = output_limit.take(&buf[0..bytes_read]);
+        if 0 < output_len {
+            let output = buf[0..output_len].to_vec();
+            user_interface_events.send(UserInterfaceEvent::CommandOutput(run_id, output))?;
         }
 
-        // TODO: Investigate the ramifications of
-        //       allowing partial output of control
-        //       characters
         //
-        // TODO: Properly handle cut-off portions
-        //       of valid, multi-byte UTF-8 characters.
-        //       Keep the portion around and prepend
-        //       it onto the next read result.
+        // Stop reading once the run has written as
+        // much as it gets to, and stop the command
+        // rather than leave it blocked on writing
+        // the rest
         //
-        let output = String::from_utf8_lossy(&output_buffer[0..bytes_read]).into_owned();
-        user_interface_events.send(UserInterfaceEvent::CommandOutput(output))?;
+        if output_limit.is_reached() {
+            pty_master = None;
+            command_exit_events.send(CommandExitEvent::KillCommand(run_id))?;
+            user_interface_events.send(UserInterfaceEvent::CommandTruncated(run_id))?;
+        }
     }
+}
+
+fn wait_for_output(pty_master: &File, timeout: Duration) -> Result<bool> {
+    let mut poll_fds = [PollFd::new(pty_master.as_raw_fd(), PollFlags::POLLIN)];
 
-    Ok(ReadCommandResult::Continue)
+    match poll(&mut poll_fds, timeout.as_millis() as i32) {
+        Ok(ready_fds) => Ok(0 < ready_fds),
+        Err(Errno::EINTR) => Ok(false),
+        Err(err) => Err(err.into()),
+    }
 }
@@ -1,9 +1,11 @@
 mod command_exit_thread;
 mod command_output_thread;
+mod terminal_resize_thread;
 mod user_input_thread;
 mod user_interface_thread;
 
 pub use command_exit_thread::{command_exit_thread, CommandExitEvent};
 pub use command_output_thread::{command_output_thread, CommandOutputEvent};
+pub use terminal_resize_thread::{resize_signals, terminal_resize_thread};
 pub use user_input_thread::{user_input_thread, UserInputEvent};
 pub use user_interface_thread::{user_interface_thread, UserInterfaceEvent};
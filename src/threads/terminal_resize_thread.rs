@@ -0,0 +1,78 @@
+use nix::sys::signal::{SigSet, Signal};
+use std::fs::File;
+use std::sync::mpsc;
+use std::thread;
+
+use super::command_exit_thread::CommandExitEvent;
+use super::command_output_thread::CommandOutputEvent;
+use super::user_input_thread::UserInputEvent;
+use super::user_interface_thread::UserInterfaceEvent;
+use crate::preview::preview_size;
+use crate::result::Result;
+
+///
+/// Signals only the terminal resize thread takes
+/// delivery of, which every other thread has to
+/// block, starting with the main thread before
+/// any others are spawned
+///
+pub fn resize_signals() -> SigSet {
+    let mut signals = SigSet::empty();
+    signals.add(Signal::SIGWINCH);
+    signals
+}
+
+///
+/// Waits for the terminal to be resized, and
+/// passes on the new size of the preview
+///
+/// Blocks in `sigwait`, so the thread has to be
+/// sent a SIGWINCH of its own to notice that
+/// the other threads stopped.
+///
+pub fn terminal_resize_thread(
+    command_exit_events: mpsc::Sender<CommandExitEvent>,
+    command_output_events: mpsc::Sender<CommandOutputEvent>,
+    user_input_events: mpsc::Sender<UserInputEvent>,
+    user_interface_events: mpsc::Sender<UserInterfaceEvent>,
+    terminal: File,
+) -> thread::JoinHandle<Result<()>> {
+    thread::spawn(move || {
+        let result = terminal_resize(&command_output_events, &user_interface_events, terminal);
+
+        // Errors mean the other thread already stopped
+        let _ = command_exit_events.send(CommandExitEvent::Stop);
+        let _ = command_output_events.send(CommandOutputEvent::Stop);
+        let _ = user_input_events.send(UserInputEvent::Stop);
+        let _ = user_interface_events.send(UserInterfaceEvent::Stop);
+
+        return result;
+    })
+}
+
+fn terminal_resize(
+    command_output_events: &mpsc::Sender<CommandOutputEvent>,
+    user_interface_events: &mpsc::Sender<UserInterfaceEvent>,
+    terminal: File,
+) -> Result<()> {
+    let signals = resize_signals();
+
+    loop {
+        signals.wait()?;
+
+        //
+        // Errors mean the other threads stopped,
+        // and so should this one
+        //
+        let (rows, cols) = preview_size(&terminal)?;
+        let is_sent = command_output_events
+            .send(CommandOutputEvent::Resized(rows, cols))
+            .is_ok()
+            && user_interface_events
+                .send(UserInterfaceEvent::Resized(rows, cols))
+                .is_ok();
+        if !is_sent {
+            return Ok(());
+        }
+    }
+}
@@ -1,165 +1,273 @@
-use nix::sys::termios;
 use nix::unistd::Pid;
 use std::fs::File;
-use std::io::{self, BufReader, Stdin};
-use std::os::fd::{AsRawFd, OwnedFd};
-use std::process::{self, Command};
-use std::sync::mpsc;
+use std::process::{self, Command, Stdio};
+use std::sync::{mpsc, Arc};
 use std::thread;
-use utf8::BufReadDecoder;
+use std::time::Duration;
 
 use super::command_exit_thread::CommandExitEvent;
 use super::command_output_thread::CommandOutputEvent;
 use super::user_interface_thread::UserInterfaceEvent;
+use crate::command::{build_command, write_piped_input, BuildCommandResult, CommandStatus};
+use crate::history::History;
+use crate::keys::KeyDecoder;
+use crate::preview::preview_size;
+use crate::prompt::{Prompt, PromptResult};
+use crate::pty::{open_pty, set_pty_size, start_session};
 use crate::result::Result;
 
 pub enum UserInputEvent {
-    CommandExited(Pid, Option<i32>),
     Stop,
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn user_input_thread(
     command_exit_events: mpsc::Sender<CommandExitEvent>,
     command_output_events: mpsc::Sender<CommandOutputEvent>,
     user_interface_events: mpsc::Sender<UserInterfaceEvent>,
     user_input_events: mpsc::Receiver<UserInputEvent>,
-    pty_master: File,
-    pty_slave_fd: OwnedFd,
-    stdin: Stdin,
-) -> thread::JoinHandle<Result<()>> {
+    tty: File,
+    terminal: File,
+    piped_input: Option<Arc<[u8]>>,
+    command_text: String,
+    shell: Option<String>,
+    debounce: Duration,
+) -> thread::JoinHandle<Result<Option<String>>> {
     thread::spawn(move || {
         let result = user_input(
             &command_exit_events,
             &command_output_events,
             &user_interface_events,
             &user_input_events,
-            pty_master,
-            pty_slave_fd,
-            stdin,
+            tty,
+            terminal,
+            piped_input,
+            command_text,
+            shell,
+            debounce,
         );
 
-        command_exit_events.send(CommandExitEvent::Stop)?;
-        command_output_events.send(CommandOutputEvent::Stop)?;
-        user_interface_events.send(UserInterfaceEvent::Stop)?;
+        // Errors mean the other thread already stopped
+        let _ = command_exit_events.send(CommandExitEvent::Stop);
+        let _ = command_output_events.send(CommandOutputEvent::Stop);
+        let _ = user_interface_events.send(UserInterfaceEvent::Stop);
 
         return result;
     })
 }
 
+#[allow(clippy::too_many_arguments)]
 fn user_input(
     command_exit_events: &mpsc::Sender<CommandExitEvent>,
     command_output_events: &mpsc::Sender<CommandOutputEvent>,
     user_interface_events: &mpsc::Sender<UserInterfaceEvent>,
     user_input_events: &mpsc::Receiver<UserInputEvent>,
-    pty_master: File,
-    pty_slave_fd: OwnedFd,
-    stdin: Stdin,
-) -> Result<()> {
-    let mut utf8_input = BufReadDecoder::new(BufReader::new(stdin));
-    let mut command_text = String::new();
-    let mut command_process: Option<process::Child> = None;
-
-    while let Some(maybe_str) = utf8_input.next_lossy() {
-        let str = maybe_str?;
-        for c in str.chars() {
-            let user_input_result = on_user_input_character(
-                &command_exit_events,
-                &command_output_events,
-                &user_interface_events,
-                &user_input_events,
-                &pty_master,
-                &pty_slave_fd,
-                &mut command_text,
-                &mut command_process,
-                c,
-            )?;
-
-            if let UserInputResult::Stop = user_input_result {
-                return Ok(());
+    tty: File,
+    terminal: File,
+    piped_input: Option<Arc<[u8]>>,
+    command_text: String,
+    shell: Option<String>,
+    debounce: Duration,
+) -> Result<Option<String>> {
+    let mut key_decoder = KeyDecoder::new(tty);
+    let mut prompt = Prompt::new(command_text, History::load());
+    let mut command_runner = CommandRunner {
+        command_exit_events,
+        command_output_events,
+        user_interface_events,
+        terminal: &terminal,
+        piped_input: piped_input.as_ref(),
+        shell: shell.as_deref(),
+        command_process: None,
+        run_id: 0,
+    };
+
+    command_runner.rerun(prompt.line_editor().text())?;
+
+    //
+    // Whether the command line changed since the
+    // command was last run
+    //
+    let mut is_command_stale = false;
+
+    loop {
+        match user_input_events.try_recv() {
+            Ok(UserInputEvent::Stop) | Err(mpsc::TryRecvError::Disconnected) => {
+                command_runner.kill()?;
+                return Ok(None);
             }
+            Err(mpsc::TryRecvError::Empty) => {}
+        }
+
+        //
+        // Debounce
+        //
+        // Only run the command once typing pauses,
+        // rather than on every key stroke. The
+        // prompt still updates on every key.
+        //
+        if is_command_stale && !key_decoder.wait_for_key(debounce)? {
+            is_command_stale = false;
+            command_runner.rerun(prompt.line_editor().text())?;
+            continue;
+        }
+
+        let key = match key_decoder.next() {
+            Some(key) => key?,
+            None => break,
+        };
+        let command_text_previous = prompt.line_editor().text().to_owned();
+
+        match prompt.handle_key(key) {
+            PromptResult::Continue => {}
+            PromptResult::Accept => {
+                command_runner.kill()?;
+                return Ok(Some(prompt.accept()));
+            }
+            PromptResult::Cancel => {
+                command_runner.kill()?;
+                return Ok(None);
+            }
+        }
+
+        user_interface_events.send(UserInterfaceEvent::PromptEdited(
+            prompt.line_editor().clone(),
+            prompt.history_search().cloned(),
+        ))?;
+
+        //
+        // Only moved the cursor, or browsed history
+        // without landing on a different command
+        //
+        if prompt.line_editor().text() != command_text_previous {
+            is_command_stale = true;
         }
     }
 
-    Ok(())
+    command_runner.kill()?;
+    Ok(None)
 }
 
-enum UserInputResult {
-    Continue,
-    Stop,
+///
+/// Runs the command being previewed, replacing
+/// whichever run came before
+///
+struct CommandRunner<'a> {
+    command_exit_events: &'a mpsc::Sender<CommandExitEvent>,
+    command_output_events: &'a mpsc::Sender<CommandOutputEvent>,
+    user_interface_events: &'a mpsc::Sender<UserInterfaceEvent>,
+
+    /// Terminal the preview is drawn on, which
+    /// commands take their window size from
+    terminal: &'a File,
+
+    piped_input: Option<&'a Arc<[u8]>>,
+    shell: Option<&'a str>,
+    command_process: Option<process::Child>,
+
+    /// Id of the latest run, so output from
+    /// earlier runs can be told apart
+    run_id: u64,
 }
 
-fn on_user_input_character(
-    command_exit_events: &mpsc::Sender<CommandExitEvent>,
-    command_output_events: &mpsc::Sender<CommandOutputEvent>,
-    user_interface_events: &mpsc::Sender<UserInterfaceEvent>,
-    user_input_events: &mpsc::Receiver<UserInputEvent>,
-    pty_master: &File,
-    pty_slave_fd: &OwnedFd,
-    command_text: &mut String,
-    command_process: &mut Option<process::Child>,
-    char: char,
-) -> Result<UserInputResult> {
-    user_interface_events.send(UserInterfaceEvent::KeyPress(char))?;
-
-    if let Some(mut cp) = command_process.take() {
-        match cp.kill() {
-            Ok(..) => {}
-            //
-            // Command already exited
-            //
-            // NOTE: Missing process may be returned as
-            // `ErrorKind::Uncategorized` which we can't
-            // match on. So, for now, treat any error
-            // as meaning the command has exited.
-            //
-            Err(..) => {}
+impl CommandRunner<'_> {
+    fn rerun(&mut self, command_text: &str) -> Result<()> {
+        //
+        // Leave the previous preview alone while the
+        // command line is incomplete, such as while
+        // typing out a quoted argument
+        //
+        let command = match build_command(self.shell, command_text) {
+            BuildCommandResult::Incomplete => return Ok(()),
+            BuildCommandResult::Empty => None,
+            BuildCommandResult::Ready(command) => Some(command),
         };
-        match user_input_events.recv()? {
-            UserInputEvent::CommandExited(..) => {}
-            UserInputEvent::Stop => return Ok(UserInputResult::Stop),
-        }
-    }
 
-    termios::tcflush(pty_master.as_raw_fd(), termios::FlushArg::TCIOFLUSH)?;
+        self.kill()?;
 
-    // TODO: Dedupe
-    match char {
-        // Escape, Carriage Return, Newline
-        '\u{1b}' | '\r' | '\n' => return Ok(UserInputResult::Stop),
-        // Backspace, Delete
-        '\u{8}' | '\u{7f}' => {
-            command_text.pop();
+        match command {
+            Some(command) => self.spawn(command),
+            None => Ok(()),
         }
-        _ => {
-            command_text.push(char);
+    }
+
+    ///
+    /// Kill the command without waiting on it,
+    /// so typing carries on even if it takes a
+    /// while to exit. The command exit thread
+    /// signals it, escalating as needed, and
+    /// reaps it in the background.
+    ///
+    fn kill(&mut self) -> Result<()> {
+        if self.command_process.take().is_some() {
+            self.command_exit_events
+                .send(CommandExitEvent::KillCommand(self.run_id))?;
         }
+
+        Ok(())
     }
-    let mut command_tokens = command_text.split_whitespace();
-    let maybe_program = command_tokens.next();
-    let args = command_tokens;
-    let program = match maybe_program {
-        Some(program) => program,
-        None => return Ok(UserInputResult::Continue),
-    };
 
-    let command_process_new = Command::new(program)
-        .args(args)
-        .stdin(pty_slave_fd.try_clone()?)
-        .stdout(pty_slave_fd.try_clone()?)
-        .stderr(pty_slave_fd.try_clone()?)
-        .spawn();
-    let command_process_new = match command_process_new {
-        Ok(new_process) => new_process,
-        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(UserInputResult::Continue),
-        Err(err) => return Err(err.into()),
-    };
+    fn spawn(&mut self, mut command: Command) -> Result<()> {
+        //
+        // Every run gets a pseudoterminal of its
+        // own, so nothing carries over from the
+        // previous run, such as terminal modes or
+        // output it didn't get to write
+        //
+        let (pty_master, pty_slave) = open_pty()?;
+        let (rows, cols) = preview_size(self.terminal)?;
+        set_pty_size(&pty_slave, rows, cols)?;
+        let command_stdin: Stdio = match self.piped_input {
+            Some(..) => Stdio::piped(),
+            None => pty_slave.try_clone()?.into(),
+        };
 
-    command_output_events.send(CommandOutputEvent::CommandStarted)?;
-    command_exit_events.send(CommandExitEvent::CommandStarted(Pid::from_raw(
-        command_process_new.id() as i32,
-    )))?;
+        self.run_id += 1;
+        self.command_output_events
+            .send(CommandOutputEvent::CommandStarted(self.run_id, pty_master))?;
+        self.user_interface_events
+            .send(UserInterfaceEvent::CommandStarted(self.run_id))?;
 
-    command_process.replace(command_process_new);
+        //
+        // Only the command holds on to the pty
+        // slave, so its master reads end of file
+        // once the command is done with it
+        //
+        start_session(&mut command);
+        let command_process = command
+            .stdin(command_stdin)
+            .stdout(pty_slave.try_clone()?)
+            .stderr(pty_slave)
+            .spawn();
+        let mut command_process = match command_process {
+            Ok(command_process) => command_process,
+            //
+            // Commands which can't be started are just
+            // another outcome of typing them out, such
+            // as `/` on the way to `/usr/bin/rg`
+            //
+            Err(err) => {
+                self.user_interface_events
+                    .send(UserInterfaceEvent::CommandStatus(
+                        self.run_id,
+                        CommandStatus::from_spawn_error(&err),
+                    ))?;
+                return Ok(());
+            }
+        };
 
-    Ok(UserInputResult::Continue)
+        if let Some(piped_input) = self.piped_input {
+            write_piped_input(&mut command_process, piped_input);
+        }
+
+        self.command_exit_events
+            .send(CommandExitEvent::CommandStarted(
+                self.run_id,
+                Pid::from_raw(command_process.id() as i32),
+            ))?;
+
+        self.command_process.replace(command_process);
+
+        Ok(())
+    }
 }
@@ -1,19 +1,53 @@
-use nix::sys::termios::{self, Termios};
-use nix::unistd::Pid;
-use std::io::{self, BufWriter, Stdout, Write};
-use std::os::fd::{AsFd, AsRawFd, OwnedFd};
+use std::fs::File;
+use std::io::{BufWriter, Write};
 use std::sync::mpsc;
 use std::thread;
+use std::time::{Duration, Instant};
 
 use super::command_exit_thread::CommandExitEvent;
 use super::command_output_thread::CommandOutputEvent;
 use super::user_input_thread::UserInputEvent;
+use crate::command::CommandStatus;
+use crate::error::Error;
+use crate::line_editor::LineEditor;
+use crate::options::Sticky;
+use crate::preview::{preview_size, Preview, PREVIEW_ROW};
+use crate::prompt::HistorySearch;
+use crate::renderer::Renderer;
 use crate::result::Result;
+use crate::run_status::RunStatus;
+
+///
+/// How often to update the status line while a
+/// command is running
+///
+const STATUS_INTERVAL: Duration = Duration::from_millis(100);
+
+///
+/// Shortest time between frames, for about 60
+/// frames per second at most
+///
+const FRAME_INTERVAL: Duration = Duration::from_millis(16);
 
 pub enum UserInterfaceEvent {
-    KeyPress(char),
-    CommandOutput(String),
-    CommandExited(Pid, Option<i32>),
+    PromptEdited(LineEditor, Option<HistorySearch>),
+    ///
+    /// Events about a command carry the id of its
+    /// run, counting up from 1, so anything from
+    /// a run which has since been replaced can be
+    /// told apart and dropped
+    ///
+    CommandStarted(u64),
+    CommandOutput(u64, Vec<u8>),
+    CommandStatus(u64, CommandStatus),
+    /// Command ran past the timeout, and is
+    /// being stopped
+    CommandTimedOut(u64),
+    /// Command wrote more output than it gets to,
+    /// and is being stopped
+    CommandTruncated(u64),
+    /// Preview now has this many rows and columns
+    Resized(usize, usize),
     Stop,
 }
 
@@ -22,15 +56,17 @@ pub fn user_interface_thread(
     command_output_events: mpsc::Sender<CommandOutputEvent>,
     user_input_events: mpsc::Sender<UserInputEvent>,
     user_interface_events: mpsc::Receiver<UserInterfaceEvent>,
-    stdout: Stdout,
-    term_config_original: Termios,
+    terminal: File,
+    command_text: String,
+    sticky: Option<Sticky>,
 ) -> thread::JoinHandle<Result<()>> {
     thread::spawn(move || {
-        let result = user_interface(&user_interface_events, stdout, term_config_original);
+        let result = user_interface(&user_interface_events, terminal, command_text, sticky);
 
-        command_exit_events.send(CommandExitEvent::Stop)?;
-        command_output_events.send(CommandOutputEvent::Stop)?;
-        user_input_events.send(UserInputEvent::Stop)?;
+        // Errors mean the other thread already stopped
+        let _ = command_exit_events.send(CommandExitEvent::Stop);
+        let _ = command_output_events.send(CommandOutputEvent::Stop);
+        let _ = user_input_events.send(UserInputEvent::Stop);
 
         return result;
     })
@@ -38,120 +74,277 @@ pub fn user_interface_thread(
 
 fn user_interface(
     user_interface_events: &mpsc::Receiver<UserInterfaceEvent>,
-    stdout: Stdout,
-    term_config_original: Termios,
+    terminal: File,
+    command_text: String,
+    sticky: Option<Sticky>,
 ) -> Result<()> {
-    let stdout_fd = stdout.as_fd().try_clone_to_owned()?;
-    let mut stdout = BufWriter::new(stdout);
+    let (rows, cols) = preview_size(&terminal)?;
+    let mut terminal = BufWriter::new(terminal);
 
-    let mut term_config_raw = term_config_original.clone();
-    termios::cfmakeraw(&mut term_config_raw);
+    // - Enable bracketed paste
+    // - Hide cursor, as the line editor draws its
+    //   own and the real one is left wherever the
+    //   preview was last drawn
+    // - Erase whole display (keep scrollback)
+    // - Move cursor to top
+    terminal.write_all("\u{1b}[?2004h\u{1b}[?25l\u{1b}[2J\u{1b}[1;1H".as_bytes())?;
 
-    let mut command_text = String::new();
-    let mut command_output = String::new();
+    let result = run_user_interface(
+        user_interface_events,
+        &mut terminal,
+        command_text,
+        sticky,
+        rows,
+        cols,
+    );
 
+    //
+    // Leave a clean terminal behind for whatever
+    // gets printed once the preview is done, even
+    // if it stopped on an error
+    //
+    // - Disable bracketed paste
+    // - Show cursor
     // - Erase whole display (keep scrollback)
     // - Move cursor to top
-    stdout.write_all("\u{1b}[2J\u{1b}[1;1H".as_bytes())?;
-    stdout.flush()?;
+    //
+    let restore_result = terminal
+        .write_all("\u{1b}[?2004l\u{1b}[?25h\u{1b}[2J\u{1b}[1;1H".as_bytes())
+        .and_then(|_| terminal.flush());
+
+    result?;
+    restore_result?;
+
+    Ok(())
+}
+
+fn run_user_interface(
+    user_interface_events: &mpsc::Receiver<UserInterfaceEvent>,
+    terminal: &mut BufWriter<File>,
+    command_text: String,
+    sticky: Option<Sticky>,
+    rows: usize,
+    cols: usize,
+) -> Result<()> {
+    let mut line_editor = LineEditor::new(command_text);
+    let mut history_search: Option<HistorySearch> = None;
+    let mut preview = Preview::new(sticky, rows, cols);
+    let mut renderer = Renderer::new(PREVIEW_ROW);
+
+    draw_line_editor(terminal, &line_editor, history_search.as_ref())?;
+
+    //
+    // Changes to the status line and preview are
+    // drawn together in frames, at most once per
+    // frame interval, so bursts of output don't
+    // each get drawn on their own
+    //
+    let mut is_frame_pending = false;
+    let mut frame_drawn_at: Option<Instant> = None;
+
+    loop {
+        let now = Instant::now();
+        let frame_due_at = frame_drawn_at.map_or(now, |frame_drawn_at| {
+            (frame_drawn_at + FRAME_INTERVAL).max(now)
+        });
+        if is_frame_pending && frame_due_at <= now {
+            draw_frame(terminal, &mut renderer, &preview)?;
+            is_frame_pending = false;
+            frame_drawn_at = Some(now);
+            continue;
+        }
+
+        //
+        // Keep the running time on the status line
+        // ticking over while waiting on the command
+        //
+        let is_running = preview.run_status().is_some_and(RunStatus::is_running);
+        let timeout = if is_frame_pending {
+            Some(frame_due_at - now)
+        } else if is_running {
+            Some(STATUS_INTERVAL)
+        } else {
+            None
+        };
+        let uie = match timeout {
+            Some(timeout) => match user_interface_events.recv_timeout(timeout) {
+                Ok(uie) => uie,
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    is_frame_pending = true;
+                    continue;
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Err(Error::ChannelRecvError),
+            },
+            None => user_interface_events.recv()?,
+        };
 
-    for uie in user_interface_events {
         let user_interface_result = handle_user_interface_event(
-            &mut stdout,
-            &stdout_fd,
-            &mut command_text,
-            &mut command_output,
-            &term_config_original,
-            &term_config_raw,
+            terminal,
+            &mut line_editor,
+            &mut history_search,
+            &mut preview,
+            &mut renderer,
             uie,
         )?;
-        if let UserInterfaceResult::Stop = user_interface_result {
-            return Ok(());
+        match user_interface_result {
+            UserInterfaceResult::Continue => {}
+            UserInterfaceResult::PreviewChanged => is_frame_pending = true,
+            UserInterfaceResult::Stop => return Ok(()),
         }
     }
-
-    Ok(())
 }
 
 enum UserInterfaceResult {
     Continue,
+    /// Preview or its status line need drawing
+    PreviewChanged,
     Stop,
 }
 
 fn handle_user_interface_event(
-    stdout: &mut BufWriter<Stdout>,
-    stdout_fd: &OwnedFd,
-    command_text: &mut String,
-    command_output: &mut String,
-    term_config_original: &Termios,
-    term_config_raw: &Termios,
+    terminal: &mut BufWriter<File>,
+    line_editor: &mut LineEditor,
+    history_search: &mut Option<HistorySearch>,
+    preview: &mut Preview,
+    renderer: &mut Renderer,
     event: UserInterfaceEvent,
 ) -> Result<UserInterfaceResult> {
     match event {
         UserInterfaceEvent::Stop => return Ok(UserInterfaceResult::Stop),
-        UserInterfaceEvent::CommandExited(..) => {
-            command_output.clear();
+        UserInterfaceEvent::CommandStarted(run_id) => {
+            preview.start(run_id, Instant::now());
+            return Ok(UserInterfaceResult::PreviewChanged);
         }
-        UserInterfaceEvent::CommandOutput(output) => {
-            command_output.push_str(&output);
-
-            //
-            // - Move down to next line
-            // - Clear display
-            //
-            stdout.write_all("\u{1b}[2;1H\u{1b}[0J".as_bytes())?;
-            stdout.flush()?;
-
-            termios::tcsetattr(
-                stdout_fd.as_raw_fd(),
-                termios::SetArg::TCSANOW,
-                &term_config_original,
-            )?;
-            io::copy(&mut command_output.as_bytes(), stdout)?;
-            stdout.flush()?;
-
-            termios::tcsetattr(
-                stdout_fd.as_raw_fd(),
-                termios::SetArg::TCSANOW,
-                &term_config_raw,
-            )?;
+        //
+        // Drop events from earlier runs still
+        // trickling in
+        //
+        UserInterfaceEvent::CommandOutput(run_id, _)
+        | UserInterfaceEvent::CommandStatus(run_id, _)
+        | UserInterfaceEvent::CommandTimedOut(run_id)
+        | UserInterfaceEvent::CommandTruncated(run_id)
+            if !preview.is_latest_run(run_id) => {}
+        UserInterfaceEvent::CommandStatus(_, command_status) => {
+            preview.set_command_status(command_status, Instant::now());
+            return Ok(UserInterfaceResult::PreviewChanged);
         }
-        UserInterfaceEvent::KeyPress(char) => {
-            // TODO: Dedupe
-            match char {
-                // Escape, Carriage Return, Newline
-                '\u{1b}' | '\r' | '\n' => {}
-                // Backspace, Delete
-                '\u{8}' | '\u{7f}' => {
-                    command_text.pop();
-                }
-                _ => {
-                    command_text.push(char);
-                }
-            }
+        UserInterfaceEvent::CommandTimedOut(_) => {
+            preview.set_timed_out();
+            return Ok(UserInterfaceResult::PreviewChanged);
+        }
+        UserInterfaceEvent::CommandTruncated(_) => {
+            preview.set_truncated();
+            return Ok(UserInterfaceResult::PreviewChanged);
+        }
+        UserInterfaceEvent::CommandOutput(_, output) => {
+            preview.add_output(&output);
+            return Ok(UserInterfaceResult::PreviewChanged);
+        }
+        UserInterfaceEvent::Resized(rows, cols) => {
+            preview.resize(rows, cols);
 
             //
-            // - Move cursor to top
-            // - Erase line
-            // - Print command
+            // Start over on a blank display, as the
+            // terminal may have rewrapped or dropped
+            // lines of its own
             //
-            // Using ANSI, not ECH or DCH in Linux console codes:
-            //
-            // https://man7.org/linux/man-pages/man4/console_codes.4.html
-            //
-            // TODO
-            //
-            // Avoid unnecessary redraws by only
-            // drawing the difference. Use
-            // `unicode_segmentation` to calculate
-            // which position to jump to.
-            //
-            stdout.write_all("\u{1b}[1;1H\u{1b}[0K".as_bytes())?;
-            stdout.write_all(command_text.as_bytes())?;
-            stdout.write_all("█".as_bytes())?;
-            stdout.flush()?;
+            terminal.write_all("\u{1b}[2J".as_bytes())?;
+            renderer.clear();
+            draw_line_editor(terminal, line_editor, history_search.as_ref())?;
+            return Ok(UserInterfaceResult::PreviewChanged);
+        }
+        UserInterfaceEvent::PromptEdited(line_editor_new, history_search_new) => {
+            *line_editor = line_editor_new;
+            *history_search = history_search_new;
+            draw_line_editor(terminal, line_editor, history_search.as_ref())?;
         }
     }
 
     Ok(UserInterfaceResult::Continue)
 }
+
+///
+/// Draw how the latest run is going on the
+/// second line, dimmed, leaving the cursor
+/// where it was
+///
+fn draw_status_line(terminal: &mut BufWriter<File>, preview: &Preview) -> Result<()> {
+    terminal.write_all("\u{1b}7\u{1b}[2;1H\u{1b}[2K".as_bytes())?;
+    if let Some(status_line) = preview.status_line(Instant::now()) {
+        write!(terminal, "\u{1b}[2m{}\u{1b}[22m", status_line)?;
+    }
+    terminal.write_all("\u{1b}8".as_bytes())?;
+
+    Ok(())
+}
+
+///
+/// Draw the status line and whatever changed in
+/// the preview, dimmed if it's left over from
+/// an earlier run
+///
+fn draw_frame(
+    terminal: &mut BufWriter<File>,
+    renderer: &mut Renderer,
+    preview: &Preview,
+) -> Result<()> {
+    draw_status_line(terminal, preview)?;
+    renderer.render(terminal, preview.shown_screen().lines(), preview.is_stale())?;
+    terminal.flush()?;
+
+    Ok(())
+}
+
+fn draw_line_editor(
+    terminal: &mut BufWriter<File>,
+    line_editor: &LineEditor,
+    history_search: Option<&HistorySearch>,
+) -> Result<()> {
+    let (before_cursor, after_cursor) = line_editor.text().split_at(line_editor.cursor());
+    let mut after_cursor = after_cursor.chars();
+    let at_cursor = after_cursor.next().unwrap_or(' ');
+
+    //
+    // - Move cursor to top
+    // - Erase line
+    // - Print command, with the character under
+    //   the cursor in reverse video
+    //
+    // Using ANSI, not ECH or DCH in Linux console codes:
+    //
+    // https://man7.org/linux/man-pages/man4/console_codes.4.html
+    //
+    // TODO
+    //
+    // Avoid unnecessary redraws by only
+    // drawing the difference. Use
+    // `unicode_segmentation` to calculate
+    // which position to jump to.
+    //
+    terminal.write_all("\u{1b}[1;1H\u{1b}[0K".as_bytes())?;
+
+    //
+    // Label the line while searching history,
+    // the way Readline does
+    //
+    if let Some(history_search) = history_search {
+        let failing = if history_search.is_failing {
+            "failing "
+        } else {
+            ""
+        };
+        write!(
+            terminal,
+            "({}reverse-i-search)`{}': ",
+            failing, history_search.query
+        )?;
+    }
+
+    terminal.write_all(before_cursor.as_bytes())?;
+    write!(terminal, "\u{1b}[7m{}\u{1b}[27m", at_cursor)?;
+    terminal.write_all(after_cursor.as_str().as_bytes())?;
+
+    terminal.flush()?;
+
+    Ok(())
+}
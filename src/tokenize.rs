@@ -0,0 +1,284 @@
+use nix::unistd::User;
+use std::env;
+use std::iter::Peekable;
+use std::mem;
+use std::str::Chars;
+
+///
+/// Split a command line into a program and its
+/// arguments, roughly following POSIX shell rules
+///
+/// - Single quotes keep everything literally
+/// - Double quotes keep everything but `$VAR`
+///   and backslash escapes of `$`, `` ` ``, `"`
+///   and `\`
+/// - Backslash escapes the next character
+/// - `~` and `~user` at the start of a word
+///   expand to home directories
+/// - `$VAR` and `${VAR}` expand to environment
+///   variables, without any further splitting
+///
+/// Returns `None` when the command line is
+/// incomplete, such as an unclosed quote or
+/// a trailing backslash.
+///
+pub fn tokenize(command_text: &str) -> Option<Vec<String>> {
+    let mut tokens: Vec<String> = Vec::new();
+    let mut token = String::new();
+    let mut in_token = false;
+    let mut chars = command_text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if in_token {
+                    tokens.push(mem::take(&mut token));
+                    in_token = false;
+                }
+            }
+            '\'' => {
+                in_token = true;
+                loop {
+                    match chars.next()? {
+                        '\'' => break,
+                        c => token.push(c),
+                    }
+                }
+            }
+            '"' => {
+                in_token = true;
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => match chars.next()? {
+                            c @ ('$' | '`' | '"' | '\\') => token.push(c),
+                            // Line continuation
+                            '\n' => {}
+                            c => {
+                                token.push('\\');
+                                token.push(c);
+                            }
+                        },
+                        '$' => expand_variable(&mut chars, &mut token)?,
+                        c => token.push(c),
+                    }
+                }
+            }
+            '\\' => match chars.next()? {
+                // Line continuation
+                '\n' => {}
+                c => {
+                    in_token = true;
+                    token.push(c);
+                }
+            },
+            '$' => {
+                //
+                // An unquoted variable which expands
+                // to nothing doesn't make a word on
+                // its own, like in a shell
+                //
+                expand_variable(&mut chars, &mut token)?;
+                in_token |= !token.is_empty();
+            }
+            '~' if !in_token => {
+                in_token = true;
+                expand_tilde(&mut chars, &mut token);
+            }
+            c => {
+                in_token = true;
+                token.push(c);
+            }
+        }
+    }
+
+    if in_token {
+        tokens.push(token);
+    }
+
+    Some(tokens)
+}
+
+fn expand_variable(chars: &mut Peekable<Chars>, token: &mut String) -> Option<()> {
+    let mut name = String::new();
+
+    match chars.peek() {
+        Some('{') => {
+            chars.next();
+            loop {
+                match chars.next()? {
+                    '}' => break,
+                    c => name.push(c),
+                }
+            }
+        }
+        Some(&c) if c == '_' || c.is_ascii_alphabetic() => {
+            while let Some(&c) = chars.peek() {
+                if c != '_' && !c.is_ascii_alphanumeric() {
+                    break;
+                }
+                name.push(c);
+                chars.next();
+            }
+        }
+        //
+        // Not a variable, such as a lone `$` or
+        // special parameters like `$?` which
+        // only make sense in a shell
+        //
+        _ => {
+            token.push('$');
+            return Some(());
+        }
+    }
+
+    if let Some(value) = env::var_os(name) {
+        token.push_str(&value.to_string_lossy());
+    }
+
+    Some(())
+}
+
+fn expand_tilde(chars: &mut Peekable<Chars>, token: &mut String) {
+    let mut user_name = String::new();
+
+    while let Some(&c) = chars.peek() {
+        if c == '/' || c.is_whitespace() {
+            break;
+        }
+        if !(c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-') {
+            //
+            // Quotes, escapes, etc. mean this isn't a
+            // plain `~user` prefix, so keep it as is
+            //
+            token.push('~');
+            token.push_str(&user_name);
+            return;
+        }
+        user_name.push(c);
+        chars.next();
+    }
+
+    let home = if user_name.is_empty() {
+        env::var("HOME").ok()
+    } else {
+        User::from_name(&user_name)
+            .ok()
+            .flatten()
+            .map(|user| user.dir.to_string_lossy().into_owned())
+    };
+
+    match home {
+        Some(home) => token.push_str(&home),
+        None => {
+            token.push('~');
+            token.push_str(&user_name);
+        }
+    }
+}
+
+///
+/// Join arguments into a command line which both
+/// `tokenize` and the shell split back into the
+/// same arguments, single quoting any argument
+/// with characters they would treat specially
+///
+pub fn join_quoted(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| {
+            let is_plain = !arg.is_empty()
+                && arg
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c));
+            match is_plain {
+                true => arg.clone(),
+                false => format!("'{}'", arg.replace('\'', "'\\''")),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{join_quoted, tokenize};
+    use std::env;
+
+    fn tokens(command_text: &str) -> Vec<String> {
+        tokenize(command_text).expect("complete command line")
+    }
+
+    #[test]
+    fn splits_on_whitespace() {
+        assert_eq!(tokens("  rg  -i\ttodo \n"), ["rg", "-i", "todo"]);
+        assert!(tokens("   ").is_empty());
+    }
+
+    #[test]
+    fn keeps_quoted_text_together() {
+        assert_eq!(tokens("rg 'a  b' \"c d\""), ["rg", "a  b", "c d"]);
+        assert_eq!(tokens("echo a'b'\"c\"d"), ["echo", "abcd"]);
+        assert_eq!(tokens("echo '' \"\""), ["echo", "", ""]);
+    }
+
+    #[test]
+    fn handles_backslash_escapes() {
+        assert_eq!(tokens(r"echo a\ b \'c\'"), ["echo", "a b", "'c'"]);
+        assert_eq!(tokens(r#"echo "\"\$\\\n""#), ["echo", r#""$\\n"#]);
+        assert_eq!(tokens("echo a\\\nb"), ["echo", "ab"]);
+    }
+
+    #[test]
+    fn keeps_single_quoted_text_literally() {
+        assert_eq!(tokens(r"echo '$HOME \ ~'"), ["echo", r"$HOME \ ~"]);
+    }
+
+    #[test]
+    fn expands_variables() {
+        env::set_var("PVW_TOKENIZE_TEST", "a b");
+        env::remove_var("PVW_TOKENIZE_TEST_UNSET");
+
+        assert_eq!(tokens("echo $PVW_TOKENIZE_TEST"), ["echo", "a b"]);
+        assert_eq!(tokens("echo x${PVW_TOKENIZE_TEST}y"), ["echo", "xa by"]);
+        assert_eq!(tokens("echo \"<$PVW_TOKENIZE_TEST>\""), ["echo", "<a b>"]);
+        assert_eq!(tokens("echo $PVW_TOKENIZE_TEST_UNSET"), ["echo"]);
+        assert_eq!(tokens("echo \"$PVW_TOKENIZE_TEST_UNSET\""), ["echo", ""]);
+        assert_eq!(tokens("echo $ $? a$"), ["echo", "$", "$?", "a$"]);
+    }
+
+    #[test]
+    fn expands_tilde_at_start_of_word() {
+        let home = env::var("HOME").unwrap();
+
+        assert_eq!(tokens("ls ~"), ["ls", home.as_str()]);
+        assert_eq!(tokens("ls ~/src"), ["ls", &format!("{}/src", home)]);
+        assert_eq!(tokens("ls a~ '~' \\~"), ["ls", "a~", "~", "~"]);
+        assert_eq!(
+            tokens("ls ~pvw-no-such-user/x"),
+            ["ls", "~pvw-no-such-user/x"]
+        );
+    }
+
+    #[test]
+    fn reports_incomplete_command_lines() {
+        assert_eq!(tokenize("echo 'abc"), None);
+        assert_eq!(tokenize("echo \"abc"), None);
+        assert_eq!(tokenize("echo abc\\"), None);
+        assert_eq!(tokenize("echo ${HOME"), None);
+    }
+
+    #[test]
+    fn joins_arguments_which_split_back_the_same() {
+        let args: Vec<String> = ["printf", "%s|", "a b", "", "it's", "~", "$HOME", "\\"]
+            .iter()
+            .map(|&arg| String::from(arg))
+            .collect();
+        let command_text = join_quoted(&args);
+
+        assert_eq!(
+            command_text,
+            "printf '%s|' 'a b' '' 'it'\\''s' '~' '$HOME' '\\'"
+        );
+        assert_eq!(tokens(&command_text), args);
+    }
+}